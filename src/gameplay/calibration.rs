@@ -0,0 +1,87 @@
+/// Taps needed before an offset is recommended.
+pub const MIN_TAPS: usize = 8;
+
+/// Works out the audio offset from taps along to a metronome.
+///
+/// Taps are timed against the audio clock without any offset applied, so
+/// on average they land late by exactly the offset the setup needs.
+pub struct Calibration {
+    beat_ms: f64,
+    first_beat_ms: f64,
+    errors: Vec<f64>,
+}
+
+impl Calibration {
+    /// Expects a click every beat at `bpm`, the first at `first_beat_ms`.
+    pub fn new(bpm: f64, first_beat_ms: f64) -> Self {
+        Self {
+            beat_ms: 60000.0 / bpm,
+            first_beat_ms,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Records a tap at `time_ms` and returns how late it was against the
+    /// nearest click. Taps before the first click are ignored.
+    pub fn tap(&mut self, time_ms: f64) -> Option<f64> {
+        let beat = ((time_ms - self.first_beat_ms) / self.beat_ms).round();
+        if beat < 0.0 {
+            return None;
+        }
+        let error = time_ms - (self.first_beat_ms + beat * self.beat_ms);
+        self.errors.push(error);
+        Some(error)
+    }
+
+    pub fn taps(&self) -> usize {
+        self.errors.len()
+    }
+
+    pub fn mean_error_ms(&self) -> Option<f64> {
+        if self.errors.is_empty() {
+            return None;
+        }
+        Some(self.errors.iter().sum::<f64>() / self.errors.len() as f64)
+    }
+
+    /// The global offset to use, once there are at least [`MIN_TAPS`] taps.
+    pub fn recommended_offset_ms(&self) -> Option<f64> {
+        if self.taps() < MIN_TAPS {
+            return None;
+        }
+        self.mean_error_ms().map(f64::round)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn taps_measure_against_the_nearest_click() {
+        // 120 BPM, so a click every 500 ms from 1000 ms on.
+        let mut calibration = Calibration::new(120.0, 1000.0);
+        assert_eq!(calibration.tap(600.0), None);
+        assert_eq!(calibration.tap(1020.0), Some(20.0));
+        assert_eq!(calibration.tap(1490.0), Some(-10.0));
+        assert_eq!(calibration.taps(), 2);
+        assert_eq!(calibration.mean_error_ms(), Some(5.0));
+    }
+
+    #[test]
+    fn offset_needs_enough_taps_and_is_rounded() {
+        let mut calibration = Calibration::new(120.0, 0.0);
+        assert_eq!(calibration.mean_error_ms(), None);
+        for beat in 0..MIN_TAPS - 1 {
+            calibration.tap(beat as f64 * 500.0 + 12.4);
+        }
+        assert_eq!(calibration.recommended_offset_ms(), None);
+
+        calibration.tap((MIN_TAPS - 1) as f64 * 500.0 + 12.4);
+        assert!((calibration.mean_error_ms().unwrap() - 12.4).abs() < 1e-9);
+        assert_eq!(calibration.recommended_offset_ms(), Some(12.0));
+
+        calibration.tap(MIN_TAPS as f64 * 500.0 + 21.4);
+        assert_eq!(calibration.recommended_offset_ms(), Some(13.0));
+    }
+}
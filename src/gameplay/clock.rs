@@ -0,0 +1,64 @@
+use crate::resource::audio::AudioPlayer;
+
+/// A source of beatmap time in milliseconds.
+pub trait Clock {
+    fn time_ms(&self) -> f64;
+}
+
+/// A clock that only moves when told to, for running gameplay without
+/// a window or an audio device.
+pub struct VirtualClock {
+    time: f64,
+    rate: f64,
+}
+
+impl VirtualClock {
+    pub fn new(start_ms: f64, rate: f64) -> Self {
+        Self {
+            time: start_ms,
+            rate,
+        }
+    }
+
+    /// Advances the clock by `real_ms` of wall time, scaled by the rate.
+    pub fn advance(&mut self, real_ms: f64) {
+        self.time += real_ms * self.rate;
+    }
+}
+
+impl Clock for VirtualClock {
+    fn time_ms(&self) -> f64 {
+        self.time
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn time_ms(&self) -> f64 {
+        (**self).time_ms()
+    }
+}
+
+/// Moves another clock back by an audio offset, so gameplay lines up with
+/// when the player hears the music rather than when it was sent out.
+pub struct OffsetClock<C> {
+    clock: C,
+    offset_ms: f64,
+}
+
+impl<C: Clock> OffsetClock<C> {
+    pub fn new(clock: C, offset_ms: f64) -> Self {
+        Self { clock, offset_ms }
+    }
+}
+
+impl<C: Clock> Clock for OffsetClock<C> {
+    fn time_ms(&self) -> f64 {
+        self.clock.time_ms() - self.offset_ms
+    }
+}
+
+impl Clock for AudioPlayer {
+    fn time_ms(&self) -> f64 {
+        self.get_time_ms()
+    }
+}
@@ -0,0 +1,86 @@
+use crate::resource::osufile::OsuFile;
+
+/// One beat of the countdown before the first object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountdownBeat {
+    Three,
+    Two,
+    One,
+    Go,
+}
+
+impl CountdownBeat {
+    /// The skin sample played on this beat.
+    pub fn sample(self) -> &'static str {
+        match self {
+            CountdownBeat::Three => "count3s",
+            CountdownBeat::Two => "count2s",
+            CountdownBeat::One => "count1s",
+            CountdownBeat::Go => "gos",
+        }
+    }
+}
+
+/// The 3-2-1-go countdown of a beatmap, timed to the beats of its first
+/// uninherited timing point.
+#[derive(Debug, Clone)]
+pub struct Countdown {
+    /// Beats in order, with their times in milliseconds.
+    beats: Vec<(f64, CountdownBeat)>,
+}
+
+impl Countdown {
+    /// Places "go" on the last beat at least one countdown beat before the
+    /// first object. `None` if the beatmap has the countdown off, or no
+    /// objects or timing to place it by.
+    pub fn new(beatmap: &OsuFile) -> Option<Self> {
+        let speed = match beatmap.general.countdown {
+            1 => 1.0,
+            2 => 2.0,
+            3 => 0.5,
+            _ => return None,
+        };
+        let first = beatmap.hit_objects.first()?.time as f64;
+        let timing = beatmap
+            .timing_points
+            .iter()
+            .find(|tp| tp.uninherited && tp.ms_per_beat > 0.0)?;
+        let beat = timing.ms_per_beat * speed;
+
+        let go = timing.offset + ((first - beat - timing.offset) / beat).floor() * beat;
+        let beats = [
+            CountdownBeat::Three,
+            CountdownBeat::Two,
+            CountdownBeat::One,
+            CountdownBeat::Go,
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, b)| (go - (3 - i) as f64 * beat, b))
+        .collect();
+        Some(Self { beats })
+    }
+
+    pub fn beats(&self) -> &[(f64, CountdownBeat)] {
+        &self.beats
+    }
+
+    /// When "three" is heard.
+    pub fn start_ms(&self) -> f64 {
+        self.beats[0].0
+    }
+}
+
+/// Where the gameplay clock starts, at or before the start of the track:
+/// `AudioLeadIn` before it, or earlier if the first object has to start
+/// approaching sooner or the countdown needs the room.
+pub fn lead_in_start_ms(beatmap: &OsuFile, preempt_ms: f64, countdown: Option<&Countdown>) -> f64 {
+    let mut start = -beatmap.general.audio_lead_in as f64;
+    if let Some(first) = beatmap.hit_objects.first() {
+        start = start.min(first.time as f64 - preempt_ms);
+    }
+    if let Some(countdown) = countdown {
+        start = start.min(countdown.start_ms());
+    }
+    start
+}
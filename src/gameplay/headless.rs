@@ -0,0 +1,269 @@
+use std::f64::consts::PI;
+
+use crate::gameplay::{
+    clock::{Clock, VirtualClock},
+    input::{InputFrame, InputSource, Keys},
+    mods::{Mods, difficulty_range},
+    score::{HitWindows, Judgement, ScoreProcessor, ScoreSummary},
+};
+use crate::resource::osufile::{HitObjectShape, OsuFile};
+
+/// Playfield centre that spinners rotate around, in osu! pixels.
+const SPINNER_CENTRE: (f32, f32) = (256.0, 192.0);
+
+pub const DEFAULT_STEP_MS: f64 = 1000.0 / 240.0;
+
+/// Judges input against the hit objects of a beatmap.
+///
+/// Objects are judged strictly in order: a press can only hit the earliest
+/// object that has not been judged yet.
+pub struct GameplayState<'a> {
+    beatmap: &'a OsuFile,
+    windows: HitWindows,
+    radius: f32,
+    spins_per_second: f64,
+    next: usize,
+    keys: Keys,
+    spin_angle: Option<f64>,
+    rotation: f64,
+    score: ScoreProcessor,
+}
+
+impl<'a> GameplayState<'a> {
+    pub fn new(beatmap: &'a OsuFile, mods: Mods) -> Self {
+        let difficulty = mods.apply(&beatmap.difficulty);
+        Self {
+            beatmap,
+            windows: HitWindows::from_od(difficulty.overall_difficulty),
+            radius: 54.4 - 4.48 * difficulty.circle_size,
+            spins_per_second: difficulty_range(difficulty.overall_difficulty, 3.0, 5.0, 7.5),
+            next: 0,
+            keys: Keys::empty(),
+            spin_angle: None,
+            rotation: 0.0,
+            score: ScoreProcessor::new(beatmap, &difficulty, mods),
+        }
+    }
+
+    /// Judges objects that can no longer be hit at `time`.
+    pub fn update(&mut self, time: f64) {
+        while let Some(ho) = self.beatmap.hit_objects.get(self.next) {
+            match ho.shape {
+                HitObjectShape::Spinner => {
+                    if time < ho.end_time() as f64 {
+                        break;
+                    }
+                    let judgement = self.judge_spinner(ho.time, ho.end_time());
+                    self.judge(judgement);
+                }
+                _ => {
+                    if time <= ho.time as f64 + self.windows.meh {
+                        break;
+                    }
+                    self.judge(Judgement::Miss);
+                }
+            }
+        }
+    }
+
+    pub fn handle_frame(&mut self, frame: InputFrame) {
+        self.update(frame.time);
+
+        let held = frame.keys.hit_buttons();
+        let pressed = held & !self.keys.hit_buttons();
+        self.keys = frame.keys;
+
+        let Some(ho) = self.beatmap.hit_objects.get(self.next) else {
+            return;
+        };
+        match ho.shape {
+            HitObjectShape::Spinner => {
+                let in_spinner = frame.time >= ho.time as f64 && frame.time <= ho.end_time() as f64;
+                if !in_spinner || held.is_empty() {
+                    self.spin_angle = None;
+                    return;
+                }
+                let angle = ((frame.y - SPINNER_CENTRE.1) as f64)
+                    .atan2((frame.x - SPINNER_CENTRE.0) as f64);
+                if let Some(last) = self.spin_angle {
+                    let mut delta = angle - last;
+                    if delta > PI {
+                        delta -= 2.0 * PI;
+                    } else if delta < -PI {
+                        delta += 2.0 * PI;
+                    }
+                    self.rotation += delta;
+                }
+                self.spin_angle = Some(angle);
+            }
+            _ => {
+                if pressed.is_empty() {
+                    return;
+                }
+                let dx = frame.x - ho.x as f32;
+                let dy = frame.y - ho.y as f32;
+                if dx * dx + dy * dy > self.radius * self.radius {
+                    return;
+                }
+                if let Some(judgement) = self.windows.judge(frame.time - ho.time as f64) {
+                    self.judge(judgement);
+                }
+            }
+        }
+    }
+
+    fn judge_spinner(&self, start: i32, end: i32) -> Judgement {
+        let required = (end - start) as f64 / 1000.0 * self.spins_per_second;
+        let spins = self.rotation.abs() / (2.0 * PI);
+        if spins >= required {
+            Judgement::Great
+        } else if spins >= required - 1.0 && spins > 0.0 {
+            Judgement::Ok
+        } else if spins >= required * 0.25 && spins > 0.0 {
+            Judgement::Meh
+        } else {
+            Judgement::Miss
+        }
+    }
+
+    fn judge(&mut self, judgement: Judgement) {
+        self.score.apply(judgement);
+        self.next += 1;
+        self.spin_angle = None;
+        self.rotation = 0.0;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.beatmap.hit_objects.len()
+    }
+
+    pub fn score(&self) -> &ScoreProcessor {
+        &self.score
+    }
+}
+
+/// Plays a beatmap to the end on a virtual clock, without a window,
+/// audio device or GL context.
+pub struct HeadlessRunner<'a, I: InputSource> {
+    beatmap: &'a OsuFile,
+    mods: Mods,
+    input: I,
+    step_ms: f64,
+}
+
+impl<'a, I: InputSource> HeadlessRunner<'a, I> {
+    pub fn new(beatmap: &'a OsuFile, mods: Mods, input: I) -> Self {
+        Self {
+            beatmap,
+            mods,
+            input,
+            step_ms: DEFAULT_STEP_MS,
+        }
+    }
+
+    /// Sets the wall-clock length of one simulation step.
+    pub fn with_step(mut self, step_ms: f64) -> Self {
+        self.step_ms = step_ms;
+        self
+    }
+
+    pub fn run(mut self) -> ScoreSummary {
+        let mut state = GameplayState::new(self.beatmap, self.mods);
+        let start = self
+            .beatmap
+            .hit_objects
+            .first()
+            .map_or(0.0, |ho| (ho.time as f64).min(0.0));
+        let mut clock = VirtualClock::new(start, self.mods.speed_multiplier());
+
+        while !state.is_finished() {
+            let now = clock.time_ms();
+            while let Some(frame) = self.input.next_frame(now) {
+                state.handle_frame(frame);
+            }
+            state.update(now);
+            clock.advance(self.step_ms);
+        }
+
+        state.score().summary(self.beatmap, self.mods)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gameplay::input::Replay;
+    use crate::resource::osufile::parse_osu_reader;
+
+    /// Circles, two of them only 30 ms apart, and a spinner.
+    const MAP: &str = "osu file format v14
+
+[General]
+AudioFilename: audio.mp3
+Mode: 0
+
+[Metadata]
+Title:Test
+Artist:Tester
+Version:Normal
+
+[Difficulty]
+HPDrainRate:5
+CircleSize:4
+OverallDifficulty:8
+ApproachRate:9
+SliderMultiplier:1.4
+SliderTickRate:1
+
+[TimingPoints]
+0,500,4,1,0,100,1,0
+
+[HitObjects]
+100,100,1000,1,0,0:0:0:0:
+300,200,1500,1,0,0:0:0:0:
+320,220,1530,1,0,0:0:0:0:
+256,192,2000,12,0,4000,0:0:0:0:
+400,300,4100,5,0,0:0:0:0:
+";
+
+    #[test]
+    fn autoplay_gets_a_perfect_score() {
+        let beatmap = parse_osu_reader(MAP.as_bytes());
+        let summary =
+            HeadlessRunner::new(&beatmap, Mods::empty(), Replay::autoplay(&beatmap)).run();
+        assert_eq!(summary.count_300, 5);
+        assert_eq!(summary.count_miss, 0);
+        assert_eq!(summary.accuracy, 1.0);
+        assert_eq!(summary.max_combo, 5);
+    }
+
+    #[test]
+    fn autoplay_releases_before_close_objects() {
+        let beatmap = parse_osu_reader(MAP.as_bytes());
+        let replay = Replay::autoplay(&beatmap);
+        let keys_at = |time: f64| {
+            let frames = replay.frames();
+            frames.iter().rev().find(|f| f.time <= time).unwrap().keys
+        };
+        // The 1500 ms circle lets go before the 1530 ms one is pressed,
+        // and that press isn't cut short by it.
+        assert!(keys_at(1529.0).is_empty());
+        assert_eq!(keys_at(1530.0), Keys::K1);
+        assert!(
+            replay
+                .frames()
+                .iter()
+                .all(|f| f.time <= 1530.0 || f.time >= 1550.0 || f.keys.contains(Keys::K1))
+        );
+    }
+
+    #[test]
+    fn empty_input_misses_everything() {
+        let beatmap = parse_osu_reader(MAP.as_bytes());
+        let summary =
+            HeadlessRunner::new(&beatmap, Mods::empty(), Replay::from_frames(Vec::new())).run();
+        assert_eq!(summary.count_miss, 5);
+        assert_eq!(summary.max_combo, 0);
+        assert_eq!(summary.accuracy, 0.0);
+    }
+}
@@ -0,0 +1,212 @@
+use std::f64::consts::PI;
+
+use crate::resource::osufile::{HitObjectShape, OsuFile};
+
+bitflags::bitflags! {
+    /// Buttons held in an input frame, using the osu! replay bits.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct Keys: u32 {
+        const M1 = 1;
+        const M2 = 2;
+        const K1 = 4;
+        const K2 = 8;
+        const SMOKE = 16;
+    }
+}
+
+impl Keys {
+    pub fn hit_buttons(&self) -> Keys {
+        *self & (Keys::M1 | Keys::M2 | Keys::K1 | Keys::K2)
+    }
+}
+
+/// Cursor position (in osu! pixels) and held keys at a point in time.
+#[derive(Debug, Clone, Copy)]
+pub struct InputFrame {
+    pub time: f64,
+    pub x: f32,
+    pub y: f32,
+    pub keys: Keys,
+}
+
+pub trait InputSource {
+    /// Returns the next frame at or before `time`, if one is due.
+    fn next_frame(&mut self, time: f64) -> Option<InputFrame>;
+}
+
+/// A recorded sequence of input frames, played back in order.
+pub struct Replay {
+    frames: Vec<InputFrame>,
+    cursor: usize,
+}
+
+const AUTO_RELEASE_MS: f64 = 50.0;
+const AUTO_SPIN_STEP_MS: f64 = 16.0;
+const AUTO_SPIN_RADIUS: f64 = 50.0;
+const AUTO_SPIN_RPM: f64 = 477.0;
+
+impl Replay {
+    pub fn from_frames(mut frames: Vec<InputFrame>) -> Self {
+        frames.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { frames, cursor: 0 }
+    }
+
+    /// Parses decompressed osu! replay data: comma-separated
+    /// `w|x|y|z` frames where `w` is the delta time since the previous frame.
+    pub fn parse_frames(data: &str) -> Self {
+        let mut frames = Vec::new();
+        let mut time = 0.0;
+        for frame in data.split(',') {
+            let parts: Vec<&str> = frame.trim().split('|').collect();
+            if parts.len() < 4 {
+                continue;
+            }
+            let delta: f64 = parts[0].parse().unwrap_or(0.0);
+            // The -12345 frame holds the RNG seed rather than input.
+            if delta == -12345.0 {
+                continue;
+            }
+            time += delta;
+            frames.push(InputFrame {
+                time,
+                x: parts[1].parse().unwrap_or(0.0),
+                y: parts[2].parse().unwrap_or(0.0),
+                keys: Keys::from_bits_truncate(parts[3].parse().unwrap_or(0)),
+            });
+        }
+        Self::from_frames(frames)
+    }
+
+    pub fn frames(&self) -> &[InputFrame] {
+        &self.frames
+    }
+
+    /// Builds a replay that hits every object perfectly.
+    pub fn autoplay(beatmap: &OsuFile) -> Self {
+        let mut frames = Vec::new();
+        let objects = &beatmap.hit_objects;
+        for (i, ho) in objects.iter().enumerate() {
+            // Alternate keys so back-to-back objects are separate presses.
+            let key = if i % 2 == 0 { Keys::K1 } else { Keys::K2 };
+            let time = ho.time as f64;
+            // Let go before the next press, or releasing both keys would
+            // cancel it when objects are closer than the release delay.
+            let release = |from: f64| match objects.get(i + 1) {
+                Some(next) => (from + AUTO_RELEASE_MS).min((from + next.time as f64) / 2.0),
+                None => from + AUTO_RELEASE_MS,
+            };
+            match ho.shape {
+                HitObjectShape::Spinner => {
+                    let end = ho.end_time() as f64;
+                    let mut t = time;
+                    while t <= end {
+                        let angle = (t - time) / 60000.0 * AUTO_SPIN_RPM * 2.0 * PI;
+                        frames.push(InputFrame {
+                            time: t,
+                            x: (256.0 + AUTO_SPIN_RADIUS * angle.cos()) as f32,
+                            y: (192.0 + AUTO_SPIN_RADIUS * angle.sin()) as f32,
+                            keys: key,
+                        });
+                        t += AUTO_SPIN_STEP_MS;
+                    }
+                    frames.push(InputFrame {
+                        time: release(end),
+                        x: 256.0,
+                        y: 192.0,
+                        keys: Keys::empty(),
+                    });
+                }
+                _ => {
+                    let (x, y) = (ho.x as f32, ho.y as f32);
+                    frames.push(InputFrame {
+                        time,
+                        x,
+                        y,
+                        keys: key,
+                    });
+                    frames.push(InputFrame {
+                        time: release(time),
+                        x,
+                        y,
+                        keys: Keys::empty(),
+                    });
+                }
+            }
+        }
+        Self::from_frames(frames)
+    }
+}
+
+impl InputSource for Replay {
+    fn next_frame(&mut self, time: f64) -> Option<InputFrame> {
+        let frame = self.frames.get(self.cursor)?;
+        if frame.time > time {
+            return None;
+        }
+        self.cursor += 1;
+        Some(*frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_frames_and_skips_the_seed() {
+        let mut replay = Replay::parse_frames(
+            "0|256|-500|0,-1|256|-500|0,10|100|200|5,15|110.5|205|0,-12345|0|0|123",
+        );
+        let frames = replay.frames().to_vec();
+        assert_eq!(frames.len(), 4);
+        assert_eq!((frames[0].time, frames[1].time), (-1.0, 0.0));
+        assert_eq!(frames[2].time, 9.0);
+        assert_eq!((frames[2].x, frames[2].y), (100.0, 200.0));
+        assert_eq!(frames[2].keys, Keys::M1 | Keys::K1);
+        assert_eq!(frames[3].time, 24.0);
+        assert_eq!(frames[3].x, 110.5);
+
+        assert!(replay.next_frame(-2.0).is_none());
+        assert_eq!(replay.next_frame(0.0).unwrap().time, -1.0);
+        assert_eq!(replay.next_frame(0.0).unwrap().time, 0.0);
+        assert!(replay.next_frame(0.0).is_none());
+    }
+
+    #[test]
+    fn parses_frames_it_was_given() {
+        let frames = [
+            InputFrame {
+                time: 100.0,
+                x: 1.5,
+                y: 2.0,
+                keys: Keys::K1,
+            },
+            InputFrame {
+                time: 116.0,
+                x: 3.0,
+                y: 4.25,
+                keys: Keys::K1 | Keys::K2,
+            },
+            InputFrame {
+                time: 150.0,
+                x: 3.0,
+                y: 4.25,
+                keys: Keys::empty(),
+            },
+        ];
+        let mut last = 0.0;
+        let data: Vec<String> = frames
+            .iter()
+            .map(|f| {
+                let delta = f.time - last;
+                last = f.time;
+                format!("{delta}|{}|{}|{}", f.x, f.y, f.keys.bits())
+            })
+            .collect();
+        let parsed = Replay::parse_frames(&data.join(","));
+        assert_eq!(parsed.frames().len(), 3);
+        for (a, b) in frames.iter().zip(parsed.frames()) {
+            assert_eq!((a.time, a.x, a.y, a.keys), (b.time, b.x, b.y, b.keys));
+        }
+    }
+}
@@ -0,0 +1,13 @@
+pub mod calibration;
+pub mod clock;
+pub mod countdown;
+pub mod difficulty;
+pub mod headless;
+pub mod input;
+pub mod mods;
+pub mod offset;
+pub mod score;
+
+pub use clock::*;
+pub use mods::Mods;
+pub use offset::Offsets;
@@ -0,0 +1,142 @@
+use crate::resource::{audio::RateMode, osufile::Difficulty};
+
+bitflags::bitflags! {
+    /// Gameplay mods, using the same bits as osu! replays and scores.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct Mods: u32 {
+        const NO_FAIL = 1;
+        const EASY = 2;
+        const HIDDEN = 8;
+        const HARD_ROCK = 16;
+        const SUDDEN_DEATH = 32;
+        const DOUBLE_TIME = 64;
+        const RELAX = 128;
+        const HALF_TIME = 256;
+        const NIGHTCORE = 512;
+        const FLASHLIGHT = 1024;
+        const AUTOPLAY = 2048;
+        const SPUN_OUT = 4096;
+        const PERFECT = 16384;
+    }
+}
+
+const ACRONYMS: [(&str, Mods); 13] = [
+    ("NF", Mods::NO_FAIL),
+    ("EZ", Mods::EASY),
+    ("HD", Mods::HIDDEN),
+    ("HR", Mods::HARD_ROCK),
+    ("SD", Mods::SUDDEN_DEATH),
+    ("DT", Mods::DOUBLE_TIME),
+    ("RX", Mods::RELAX),
+    ("HT", Mods::HALF_TIME),
+    ("NC", Mods::NIGHTCORE),
+    ("FL", Mods::FLASHLIGHT),
+    ("AT", Mods::AUTOPLAY),
+    ("SO", Mods::SPUN_OUT),
+    ("PF", Mods::PERFECT),
+];
+
+impl Mods {
+    /// Parses a string of two-letter acronyms such as `"HDDT"`.
+    /// Returns `None` if any acronym is unknown.
+    pub fn from_acronyms(s: &str) -> Option<Mods> {
+        let s = s.trim().to_ascii_uppercase();
+        if !s.len().is_multiple_of(2) {
+            return None;
+        }
+        let mut mods = Mods::empty();
+        for i in (0..s.len()).step_by(2) {
+            let acronym = s.get(i..i + 2)?;
+            let (_, m) = ACRONYMS.iter().find(|(a, _)| *a == acronym)?;
+            mods |= *m;
+        }
+        Some(mods)
+    }
+
+    pub fn acronyms(&self) -> String {
+        ACRONYMS
+            .iter()
+            .filter(|(_, m)| self.contains(*m))
+            .map(|(a, _)| *a)
+            .collect()
+    }
+
+    /// Playback rate of the track.
+    pub fn speed_multiplier(&self) -> f64 {
+        if self.intersects(Mods::DOUBLE_TIME | Mods::NIGHTCORE) {
+            1.5
+        } else if self.contains(Mods::HALF_TIME) {
+            0.75
+        } else {
+            1.0
+        }
+    }
+
+    /// Nightcore raises the pitch with the tempo; DT and HT keep it.
+    pub fn rate_mode(&self) -> RateMode {
+        if self.contains(Mods::NIGHTCORE) {
+            RateMode::Varispeed
+        } else {
+            RateMode::TimeStretch
+        }
+    }
+
+    pub fn score_multiplier(&self) -> f64 {
+        let mut mul = 1.0;
+        if self.contains(Mods::NO_FAIL) {
+            mul *= 0.5;
+        }
+        if self.contains(Mods::EASY) {
+            mul *= 0.5;
+        }
+        if self.contains(Mods::HALF_TIME) {
+            mul *= 0.3;
+        }
+        if self.contains(Mods::HIDDEN) {
+            mul *= 1.06;
+        }
+        if self.contains(Mods::HARD_ROCK) {
+            mul *= 1.06;
+        }
+        if self.intersects(Mods::DOUBLE_TIME | Mods::NIGHTCORE) {
+            mul *= 1.12;
+        }
+        if self.contains(Mods::FLASHLIGHT) {
+            mul *= 1.12;
+        }
+        if self.contains(Mods::SPUN_OUT) {
+            mul *= 0.9;
+        }
+        mul
+    }
+
+    /// Returns the difficulty settings with HR/EZ applied.
+    pub fn apply(&self, difficulty: &Difficulty) -> Difficulty {
+        let mut d = difficulty.clone();
+        if self.contains(Mods::HARD_ROCK) {
+            d.circle_size = (d.circle_size * 1.3).min(10.0);
+            d.approach_rate = (d.approach_rate * 1.4).min(10.0);
+            d.overall_difficulty = (d.overall_difficulty * 1.4).min(10.0);
+            d.hp_drain_rate = (d.hp_drain_rate * 1.4).min(10.0);
+        } else if self.contains(Mods::EASY) {
+            d.circle_size *= 0.5;
+            d.approach_rate *= 0.5;
+            d.overall_difficulty *= 0.5;
+            d.hp_drain_rate *= 0.5;
+        }
+        d
+    }
+}
+
+/// Maps a 0-10 difficulty value onto a range, the way osu! derives
+/// preempt and hit windows from AR and OD.
+pub fn difficulty_range(value: f32, min: f64, mid: f64, max: f64) -> f64 {
+    let value = value as f64;
+    if value > 5.0 {
+        mid + (max - mid) * (value - 5.0) / 5.0
+    } else if value < 5.0 {
+        mid - (mid - min) * (5.0 - value) / 5.0
+    } else {
+        mid
+    }
+}
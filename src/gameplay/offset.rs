@@ -0,0 +1,92 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Audio offsets in milliseconds, saved between runs. A positive offset
+/// means the audio is heard that much later than the clock says, so
+/// gameplay time is moved back by it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Offsets {
+    /// Applies to every beatmap, to make up for the output latency of the
+    /// player's setup.
+    pub global_ms: f64,
+    /// Per-beatmap corrections on top of the global offset, by the MD5
+    /// of the `.osu` file.
+    local_ms: HashMap<String, f64>,
+}
+
+impl Offsets {
+    /// Loads saved offsets. A missing file gives no offsets.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read(path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn local_ms(&self, md5: &str) -> f64 {
+        self.local_ms.get(md5).copied().unwrap_or(0.0)
+    }
+
+    pub fn set_local_ms(&mut self, md5: &str, ms: f64) {
+        if ms == 0.0 {
+            self.local_ms.remove(md5);
+        } else {
+            self.local_ms.insert(md5.to_string(), ms);
+        }
+    }
+
+    /// The offset to play a beatmap with.
+    pub fn total_ms(&self, md5: &str) -> f64 {
+        self.global_ms + self.local_ms(md5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn local_offsets_add_to_the_global_one() {
+        let mut offsets = Offsets {
+            global_ms: 25.0,
+            ..Default::default()
+        };
+        offsets.set_local_ms("abc", -10.0);
+        assert_eq!(offsets.total_ms("abc"), 15.0);
+        assert_eq!(offsets.total_ms("def"), 25.0);
+
+        // A zero offset isn't kept.
+        offsets.set_local_ms("abc", 0.0);
+        assert!(offsets.local_ms.is_empty());
+    }
+
+    #[test]
+    fn offsets_survive_a_save() {
+        let dir = TempDir::new("offsets");
+        let path = dir.join("offsets.json");
+        assert_eq!(Offsets::load(&path).unwrap().global_ms, 0.0);
+
+        let mut offsets = Offsets {
+            global_ms: 12.0,
+            ..Default::default()
+        };
+        offsets.set_local_ms("abc", 5.0);
+        offsets.save(&path).unwrap();
+        let loaded = Offsets::load(&path).unwrap();
+        assert_eq!(loaded.global_ms, 12.0);
+        assert_eq!(loaded.local_ms("abc"), 5.0);
+    }
+}
@@ -0,0 +1,163 @@
+use serde::Serialize;
+
+use crate::gameplay::mods::{Mods, difficulty_range};
+use crate::resource::osufile::{Difficulty, OsuFile};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Judgement {
+    Great,
+    Ok,
+    Meh,
+    Miss,
+}
+
+impl Judgement {
+    pub fn hit_value(&self) -> u32 {
+        match self {
+            Judgement::Great => 300,
+            Judgement::Ok => 100,
+            Judgement::Meh => 50,
+            Judgement::Miss => 0,
+        }
+    }
+}
+
+/// Maximum offsets in milliseconds for each judgement.
+#[derive(Debug, Clone, Copy)]
+pub struct HitWindows {
+    pub great: f64,
+    pub ok: f64,
+    pub meh: f64,
+}
+
+impl HitWindows {
+    pub fn from_od(od: f32) -> Self {
+        Self {
+            great: difficulty_range(od, 80.0, 50.0, 20.0),
+            ok: difficulty_range(od, 140.0, 100.0, 60.0),
+            meh: difficulty_range(od, 200.0, 150.0, 100.0),
+        }
+    }
+
+    pub fn judge(&self, offset: f64) -> Option<Judgement> {
+        let offset = offset.abs();
+        if offset <= self.great {
+            Some(Judgement::Great)
+        } else if offset <= self.ok {
+            Some(Judgement::Ok)
+        } else if offset <= self.meh {
+            Some(Judgement::Meh)
+        } else {
+            None
+        }
+    }
+}
+
+/// Final result of a play.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoreSummary {
+    pub artist: String,
+    pub title: String,
+    pub version: String,
+    pub mods: String,
+    pub score: u64,
+    pub accuracy: f64,
+    pub max_combo: u32,
+    pub count_300: u32,
+    pub count_100: u32,
+    pub count_50: u32,
+    pub count_miss: u32,
+}
+
+/// Accumulates judgements into score, combo and accuracy using the
+/// osu!stable (ScoreV1) formula.
+pub struct ScoreProcessor {
+    difficulty_multiplier: f64,
+    mod_multiplier: f64,
+    score: u64,
+    combo: u32,
+    max_combo: u32,
+    counts: [u32; 4],
+}
+
+impl ScoreProcessor {
+    pub fn new(beatmap: &OsuFile, difficulty: &Difficulty, mods: Mods) -> Self {
+        Self {
+            difficulty_multiplier: Self::difficulty_multiplier(beatmap, difficulty),
+            mod_multiplier: mods.score_multiplier(),
+            score: 0,
+            combo: 0,
+            max_combo: 0,
+            counts: [0; 4],
+        }
+    }
+
+    fn difficulty_multiplier(beatmap: &OsuFile, difficulty: &Difficulty) -> f64 {
+        let objects = &beatmap.hit_objects;
+        let drain_secs = match (objects.first(), objects.last()) {
+            (Some(first), Some(last)) => (last.end_time() - first.time) as f64 / 1000.0,
+            _ => 0.0,
+        };
+        let density = if drain_secs > 0.0 {
+            (objects.len() as f64 / drain_secs * 8.0).clamp(0.0, 16.0)
+        } else {
+            0.0
+        };
+        let points = difficulty.hp_drain_rate as f64
+            + difficulty.circle_size as f64
+            + difficulty.overall_difficulty as f64
+            + density;
+        (points / 38.0 * 5.0).round()
+    }
+
+    pub fn apply(&mut self, judgement: Judgement) {
+        let hit_value = judgement.hit_value() as f64;
+        let combo_bonus =
+            self.combo as f64 * self.difficulty_multiplier * self.mod_multiplier / 25.0;
+        self.score += (hit_value + hit_value * combo_bonus) as u64;
+
+        if judgement == Judgement::Miss {
+            self.combo = 0;
+        } else {
+            self.combo += 1;
+            self.max_combo = self.max_combo.max(self.combo);
+        }
+
+        let slot = match judgement {
+            Judgement::Great => 0,
+            Judgement::Ok => 1,
+            Judgement::Meh => 2,
+            Judgement::Miss => 3,
+        };
+        self.counts[slot] += 1;
+    }
+
+    pub fn combo(&self) -> u32 {
+        self.combo
+    }
+
+    pub fn accuracy(&self) -> f64 {
+        let [c300, c100, c50, miss] = self.counts.map(|c| c as f64);
+        let total = c300 + c100 + c50 + miss;
+        if total == 0.0 {
+            return 1.0;
+        }
+        (c300 * 300.0 + c100 * 100.0 + c50 * 50.0) / (total * 300.0)
+    }
+
+    pub fn summary(&self, beatmap: &OsuFile, mods: Mods) -> ScoreSummary {
+        ScoreSummary {
+            artist: beatmap.metadata.artist.clone(),
+            title: beatmap.metadata.title.clone(),
+            version: beatmap.metadata.version.clone(),
+            mods: mods.acronyms(),
+            score: self.score,
+            accuracy: self.accuracy(),
+            max_combo: self.max_combo,
+            count_300: self.counts[0],
+            count_100: self.counts[1],
+            count_50: self.counts[2],
+            count_miss: self.counts[3],
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::gameplay::{Mods, difficulty::star_rating};
+use crate::resource::osufile::{HitObjectShape, OsuFile};
+
+/// What the library remembers about one difficulty, without keeping the
+/// hit objects around.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub path: PathBuf,
+    /// Modification time in seconds since the Unix epoch, used together with
+    /// the size to decide whether the file needs to be parsed again.
+    pub mtime: u64,
+    pub size: u64,
+    pub md5: String,
+    pub sha256: String,
+
+    pub title: String,
+    pub title_unicode: String,
+    pub artist: String,
+    pub artist_unicode: String,
+    pub creator: String,
+    pub version: String,
+    pub source: String,
+    pub tags: String,
+    pub beatmap_id: i32,
+    pub beatmap_set_id: i32,
+    pub audio_filename: String,
+    pub mode: i32,
+
+    pub hp_drain_rate: f32,
+    pub circle_size: f32,
+    pub overall_difficulty: f32,
+    pub approach_rate: f32,
+
+    pub bpm_min: f64,
+    pub bpm_max: f64,
+    pub length_ms: i32,
+    pub circles: u32,
+    pub sliders: u32,
+    pub spinners: u32,
+    pub stars: f64,
+}
+
+impl IndexEntry {
+    pub fn new(path: PathBuf, mtime: u64, size: u64, beatmap: &OsuFile) -> Self {
+        let bpms = beatmap
+            .timing_points
+            .iter()
+            .filter(|tp| tp.uninherited && tp.ms_per_beat > 0.0)
+            .map(|tp| 60000.0 / tp.ms_per_beat);
+        let bpm_min = bpms.clone().fold(f64::INFINITY, f64::min);
+        let bpm_max = bpms.fold(0.0, f64::max);
+
+        let objects = &beatmap.hit_objects;
+        let length_ms = match (objects.first(), objects.last()) {
+            (Some(first), Some(last)) => last.end_time() - first.time,
+            _ => 0,
+        };
+        let count = |shape: fn(&HitObjectShape) -> bool| {
+            objects.iter().filter(|ho| shape(&ho.shape)).count() as u32
+        };
+
+        let m = &beatmap.metadata;
+        let d = &beatmap.difficulty;
+        Self {
+            path,
+            mtime,
+            size,
+            md5: beatmap.md5.clone(),
+            sha256: beatmap.sha256.clone(),
+            title: m.title.clone(),
+            title_unicode: m.title_unicode.clone(),
+            artist: m.artist.clone(),
+            artist_unicode: m.artist_unicode.clone(),
+            creator: m.creator.clone(),
+            version: m.version.clone(),
+            source: m.source.clone(),
+            tags: m.tags.clone(),
+            beatmap_id: m.beatmap_id,
+            beatmap_set_id: m.beatmap_set_id,
+            audio_filename: beatmap.general.audio_filename.clone(),
+            mode: beatmap.general.mode,
+            hp_drain_rate: d.hp_drain_rate,
+            circle_size: d.circle_size,
+            overall_difficulty: d.overall_difficulty,
+            approach_rate: d.approach_rate,
+            bpm_min: if bpm_min.is_finite() { bpm_min } else { 0.0 },
+            bpm_max,
+            length_ms,
+            circles: count(|s| matches!(s, HitObjectShape::Circle)),
+            sliders: count(|s| matches!(s, HitObjectShape::Slider)),
+            spinners: count(|s| matches!(s, HitObjectShape::Spinner)),
+            stars: star_rating(beatmap, Mods::empty()),
+        }
+    }
+
+    /// The audio file next to the `.osu`, if the beatmap names one.
+    pub fn audio_path(&self) -> Option<PathBuf> {
+        if self.audio_filename.is_empty() {
+            return None;
+        }
+        Some(self.path.parent()?.join(&self.audio_filename))
+    }
+}
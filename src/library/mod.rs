@@ -0,0 +1,343 @@
+pub mod index;
+pub mod search;
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::resource::{
+    audio::{decode::decode_all, loudness::Loudness},
+    file_stamp,
+    osufile::{OsuFile, parse_osu_reader},
+};
+
+pub use index::IndexEntry;
+pub use search::{Query, QueryError};
+
+/// Bumped whenever the cached entries change, so stale caches are rebuilt.
+const CACHE_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    entries: Vec<IndexEntry>,
+    /// Missing from older caches, which are thrown away anyway.
+    #[serde(default)]
+    audio: Vec<AudioEntry>,
+}
+
+/// What the library remembers about an audio file, which the difficulties
+/// of a set share.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AudioEntry {
+    path: PathBuf,
+    mtime: u64,
+    size: u64,
+    /// `None` for silent or undecodable files, which are left as they are.
+    loudness: Option<Loudness>,
+}
+
+/// Counts of what a rescan did to the index.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ScanStats {
+    pub added: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub failed: usize,
+    /// Audio files whose loudness was measured.
+    pub measured: usize,
+}
+
+/// The beatmaps known to the game, looked up by the hashes that replays,
+/// scores and collections refer to them by.
+#[derive(Debug, Default)]
+pub struct BeatmapLibrary {
+    entries: Vec<IndexEntry>,
+    by_md5: HashMap<String, PathBuf>,
+    audio: HashMap<PathBuf, AudioEntry>,
+}
+
+impl BeatmapLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the index cache. A missing or outdated cache gives an empty
+    /// library, which the next scan fills in.
+    pub fn load(cache: &Path) -> io::Result<Self> {
+        let data = match fs::read(cache) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::new()),
+            Err(e) => return Err(e),
+        };
+        let file: CacheFile = serde_json::from_slice(&data)?;
+        let mut library = Self::new();
+        if file.version == CACHE_VERSION {
+            library.entries = file.entries;
+            library.audio = file
+                .audio
+                .into_iter()
+                .map(|a| (a.path.clone(), a))
+                .collect();
+            library.rebuild_hashes();
+        }
+        Ok(library)
+    }
+
+    pub fn save(&self, cache: &Path) -> io::Result<()> {
+        let file = CacheFile {
+            version: CACHE_VERSION,
+            entries: self.entries.clone(),
+            audio: self.audio.values().cloned().collect(),
+        };
+        fs::write(cache, serde_json::to_vec(&file)?)
+    }
+
+    /// Recursively scans a songs folder. Only `.osu` files whose size or
+    /// modification time changed since the last scan are parsed again, and
+    /// entries under `songs_dir` whose file is gone are dropped.
+    ///
+    /// The loudness of each audio file is measured too, which means
+    /// decoding it, so that is also only redone for changed files.
+    pub fn scan(&mut self, songs_dir: &Path) -> io::Result<ScanStats> {
+        let paths = find_osu_files(songs_dir)?;
+        let mut stats = ScanStats::default();
+        let mut old: HashMap<PathBuf, IndexEntry> = HashMap::new();
+        let mut entries = Vec::new();
+        for entry in self.entries.drain(..) {
+            if entry.path.starts_with(songs_dir) {
+                old.insert(entry.path.clone(), entry);
+            } else {
+                entries.push(entry);
+            }
+        }
+
+        for path in paths {
+            // Kept if the file can't be read this time.
+            let previous = old.remove(&path);
+            let Ok((mtime, size)) = file_stamp(&path) else {
+                stats.failed += 1;
+                entries.extend(previous);
+                continue;
+            };
+            if let Some(entry) = &previous
+                && entry.mtime == mtime
+                && entry.size == size
+            {
+                stats.unchanged += 1;
+                entries.extend(previous);
+                continue;
+            }
+            let Ok(bytes) = fs::read(&path) else {
+                stats.failed += 1;
+                entries.extend(previous);
+                continue;
+            };
+            if previous.is_some() {
+                stats.updated += 1;
+            } else {
+                stats.added += 1;
+            }
+            let beatmap = parse_osu_reader(bytes.as_slice());
+            entries.push(IndexEntry::new(path, mtime, size, &beatmap));
+        }
+        stats.removed = old.len();
+
+        self.entries = entries;
+        self.rebuild_hashes();
+        stats.measured = self.measure_audio(songs_dir);
+        Ok(stats)
+    }
+
+    /// Measures audio files under `songs_dir` that are new or changed,
+    /// and forgets ones no beatmap uses any more. Returns how many were
+    /// measured.
+    fn measure_audio(&mut self, songs_dir: &Path) -> usize {
+        let used: HashSet<PathBuf> = self
+            .entries
+            .iter()
+            .filter(|e| e.path.starts_with(songs_dir))
+            .filter_map(IndexEntry::audio_path)
+            .collect();
+        self.audio
+            .retain(|path, _| !path.starts_with(songs_dir) || used.contains(path));
+
+        let mut measured = 0;
+        for path in used {
+            let Ok((mtime, size)) = file_stamp(&path) else {
+                self.audio.remove(&path);
+                continue;
+            };
+            if self
+                .audio
+                .get(&path)
+                .is_some_and(|a| a.mtime == mtime && a.size == size)
+            {
+                continue;
+            }
+            // A file that can't be decoded now is tried again next scan.
+            let Ok(audio) = decode_all(&path) else {
+                self.audio.remove(&path);
+                continue;
+            };
+            let loudness = Loudness::measure(&audio);
+            measured += 1;
+            self.audio.insert(
+                path.clone(),
+                AudioEntry {
+                    path,
+                    mtime,
+                    size,
+                    loudness,
+                },
+            );
+        }
+        measured
+    }
+
+    /// Adds or replaces a single beatmap, e.g. right after an import.
+    pub fn add(&mut self, path: &Path, beatmap: &OsuFile) {
+        let (mtime, size) = file_stamp(path).unwrap_or((0, 0));
+        self.entries.retain(|e| e.path != path);
+        // The file may have been edited since, leaving its old hash behind.
+        self.by_md5.retain(|_, p| p != path);
+        self.entries
+            .push(IndexEntry::new(path.to_path_buf(), mtime, size, beatmap));
+        self.by_md5.insert(beatmap.md5.clone(), path.to_path_buf());
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// Loudness of a beatmap's audio as of the last scan.
+    pub fn loudness(&self, entry: &IndexEntry) -> Option<Loudness> {
+        self.audio.get(&entry.audio_path()?)?.loudness
+    }
+
+    /// Gain that normalizes a beatmap's audio to `target_lufs`, or 1 if
+    /// its loudness isn't known.
+    pub fn normalization_gain(&self, entry: &IndexEntry, target_lufs: f64) -> f32 {
+        self.loudness(entry).map_or(1.0, |l| l.gain(target_lufs))
+    }
+
+    /// Runs a search query such as `ar>9 stars>5 sort=-bpm`.
+    pub fn search(&self, query: &str) -> Result<Vec<&IndexEntry>, QueryError> {
+        Ok(Query::parse(query)?.apply(&self.entries))
+    }
+
+    /// Finds the `.osu` file whose MD5 matches, e.g. the one a replay was
+    /// played on. The hash is compared case-insensitively.
+    pub fn path_for_md5(&self, md5: &str) -> Option<&Path> {
+        self.by_md5
+            .get(&md5.to_ascii_lowercase())
+            .map(PathBuf::as_path)
+    }
+
+    fn rebuild_hashes(&mut self) {
+        self.by_md5 = self
+            .entries
+            .iter()
+            .map(|e| (e.md5.clone(), e.path.clone()))
+            .collect();
+    }
+}
+
+fn find_osu_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("osu"))
+            {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{TempDir, write_wav};
+
+    #[test]
+    fn scan_survives_truncated_files() {
+        let songs = TempDir::new("library_truncated");
+        let set = songs.join("set");
+        fs::create_dir_all(&set).unwrap();
+        fs::write(
+            set.join("cut.osu"),
+            "osu file format v14\n\n[TimingPoints]\n0,500,4\n\n[HitObjects]\n256,192\n",
+        )
+        .unwrap();
+
+        let mut library = BeatmapLibrary::new();
+        let stats = library.scan(songs.path()).unwrap();
+        assert_eq!((stats.added, stats.failed), (1, 0));
+        assert_eq!(library.entries().len(), 1);
+    }
+
+    #[test]
+    fn undecodable_audio_is_measured_again() {
+        let songs = TempDir::new("library_audio");
+        let set = songs.join("set");
+        fs::create_dir_all(&set).unwrap();
+        fs::write(
+            set.join("map.osu"),
+            "osu file format v14\n\n[General]\nAudioFilename: audio.wav\n",
+        )
+        .unwrap();
+        fs::write(set.join("audio.wav"), "not audio").unwrap();
+
+        let mut library = BeatmapLibrary::new();
+        let stats = library.scan(songs.path()).unwrap();
+        assert_eq!(stats.measured, 0);
+        assert!(library.audio.is_empty());
+
+        write_wav(&set.join("audio.wav"), 8000, 1, &[0.5; 8000]);
+        let stats = library.scan(songs.path()).unwrap();
+        assert_eq!(stats.measured, 1);
+        assert_eq!(library.audio.len(), 1);
+    }
+
+    #[test]
+    fn failed_walk_keeps_the_entries() {
+        let dir = TempDir::new("library_missing");
+        let songs = dir.join("missing");
+        let beatmap = parse_osu_reader("osu file format v14\n".as_bytes());
+        let mut library = BeatmapLibrary::new();
+        library.add(&songs.join("set/map.osu"), &beatmap);
+
+        assert!(library.scan(&songs).is_err());
+        assert_eq!(library.entries().len(), 1);
+        assert!(library.path_for_md5(&beatmap.md5).is_some());
+    }
+
+    #[test]
+    fn add_forgets_the_old_hash() {
+        let path = Path::new("songs/set/map.osu");
+        let old = parse_osu_reader("osu file format v14\n".as_bytes());
+        let new = parse_osu_reader("osu file format v14\n\n[Metadata]\nTitle:Edited\n".as_bytes());
+
+        let mut library = BeatmapLibrary::new();
+        library.add(path, &old);
+        library.add(path, &new);
+        assert_eq!(library.entries().len(), 1);
+        assert_eq!(library.path_for_md5(&old.md5), None);
+        assert_eq!(library.path_for_md5(&new.md5), Some(path));
+    }
+}
@@ -0,0 +1,540 @@
+use std::{cmp::Ordering, error::Error, fmt};
+
+use crate::library::IndexEntry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    NotEq,
+    Less,
+    LessEq,
+    Greater,
+    GreaterEq,
+}
+
+impl Op {
+    fn compare(&self, ord: Ordering) -> bool {
+        match self {
+            Op::Eq => ord == Ordering::Equal,
+            Op::NotEq => ord != Ordering::Equal,
+            Op::Less => ord == Ordering::Less,
+            Op::LessEq => ord != Ordering::Greater,
+            Op::Greater => ord == Ordering::Greater,
+            Op::GreaterEq => ord != Ordering::Less,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberField {
+    ApproachRate,
+    CircleSize,
+    OverallDifficulty,
+    HpDrainRate,
+    Stars,
+    /// Compared against the highest BPM of the map.
+    Bpm,
+    /// Length in seconds.
+    Length,
+}
+
+impl NumberField {
+    fn value(&self, entry: &IndexEntry) -> f64 {
+        match self {
+            NumberField::ApproachRate => entry.approach_rate as f64,
+            NumberField::CircleSize => entry.circle_size as f64,
+            NumberField::OverallDifficulty => entry.overall_difficulty as f64,
+            NumberField::HpDrainRate => entry.hp_drain_rate as f64,
+            NumberField::Stars => entry.stars,
+            NumberField::Bpm => entry.bpm_max,
+            NumberField::Length => entry.length_ms as f64 / 1000.0,
+        }
+    }
+
+    /// How close two values must be to count as equal, about half of the
+    /// precision the value is shown with.
+    fn tolerance(&self) -> f64 {
+        match self {
+            NumberField::Stars => 0.005,
+            NumberField::Bpm | NumberField::Length => 0.5,
+            _ => 0.05,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextField {
+    Artist,
+    Creator,
+    Source,
+    Tags,
+    Title,
+    Version,
+}
+
+impl TextField {
+    fn values<'a>(&self, entry: &'a IndexEntry) -> [&'a str; 2] {
+        match self {
+            TextField::Artist => [&entry.artist, &entry.artist_unicode],
+            TextField::Creator => [&entry.creator, ""],
+            TextField::Source => [&entry.source, ""],
+            TextField::Tags => [&entry.tags, ""],
+            TextField::Title => [&entry.title, &entry.title_unicode],
+            TextField::Version => [&entry.version, ""],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Number {
+        field: NumberField,
+        op: Op,
+        value: f64,
+    },
+    /// Case-insensitive substring match; only `=` and `!=` are allowed.
+    Text {
+        field: TextField,
+        negate: bool,
+        value: String,
+    },
+    Mode {
+        negate: bool,
+        mode: i32,
+    },
+}
+
+impl Filter {
+    pub fn matches(&self, entry: &IndexEntry) -> bool {
+        match self {
+            Filter::Number { field, op, value } => {
+                let actual = field.value(entry);
+                let ord = if (actual - value).abs() < field.tolerance() {
+                    Ordering::Equal
+                } else {
+                    actual.total_cmp(value)
+                };
+                op.compare(ord)
+            }
+            Filter::Text {
+                field,
+                negate,
+                value,
+            } => {
+                let found = field
+                    .values(entry)
+                    .iter()
+                    .any(|v| v.to_lowercase().contains(value));
+                found != *negate
+            }
+            Filter::Mode { negate, mode } => (entry.mode == *mode) != *negate,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Artist,
+    Bpm,
+    Creator,
+    DateModified,
+    Length,
+    Stars,
+    Title,
+    ApproachRate,
+    CircleSize,
+    OverallDifficulty,
+    HpDrainRate,
+}
+
+impl SortKey {
+    fn compare(&self, a: &IndexEntry, b: &IndexEntry) -> Ordering {
+        let text = |x: &str, y: &str| x.to_lowercase().cmp(&y.to_lowercase());
+        match self {
+            SortKey::Artist => text(&a.artist, &b.artist),
+            SortKey::Bpm => a.bpm_max.total_cmp(&b.bpm_max),
+            SortKey::Creator => text(&a.creator, &b.creator),
+            SortKey::DateModified => a.mtime.cmp(&b.mtime),
+            SortKey::Length => a.length_ms.cmp(&b.length_ms),
+            SortKey::Stars => a.stars.total_cmp(&b.stars),
+            SortKey::Title => text(&a.title, &b.title),
+            SortKey::ApproachRate => a.approach_rate.total_cmp(&b.approach_rate),
+            SortKey::CircleSize => a.circle_size.total_cmp(&b.circle_size),
+            SortKey::OverallDifficulty => a.overall_difficulty.total_cmp(&b.overall_difficulty),
+            SortKey::HpDrainRate => a.hp_drain_rate.total_cmp(&b.hp_drain_rate),
+        }
+    }
+}
+
+/// A syntax error in a search query, with the byte offset it was found at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at {})", self.message, self.position)
+    }
+}
+
+impl Error for QueryError {}
+
+/// A parsed osu!-style search, e.g. `camellia ar>9 stars>=5.5 sort=-bpm`.
+///
+/// Free text words must all appear in the title, artist, creator, version,
+/// tags or source (including the unicode variants). `key<op>value` tokens
+/// with a known key are filters; `sort=key` (or `sort=-key` for
+/// descending) orders the results.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    pub terms: Vec<String>,
+    pub filters: Vec<Filter>,
+    pub sort: Option<(SortKey, bool)>,
+}
+
+enum Key {
+    Number(NumberField),
+    Text(TextField),
+    Mode,
+    Sort,
+}
+
+fn parse_key(key: &str) -> Option<Key> {
+    Some(match key {
+        "ar" => Key::Number(NumberField::ApproachRate),
+        "cs" => Key::Number(NumberField::CircleSize),
+        "od" => Key::Number(NumberField::OverallDifficulty),
+        "hp" => Key::Number(NumberField::HpDrainRate),
+        "star" | "stars" => Key::Number(NumberField::Stars),
+        "bpm" => Key::Number(NumberField::Bpm),
+        "length" => Key::Number(NumberField::Length),
+        "artist" => Key::Text(TextField::Artist),
+        "creator" | "mapper" => Key::Text(TextField::Creator),
+        "source" => Key::Text(TextField::Source),
+        "tag" | "tags" => Key::Text(TextField::Tags),
+        "title" => Key::Text(TextField::Title),
+        "diff" | "version" => Key::Text(TextField::Version),
+        "mode" => Key::Mode,
+        "sort" => Key::Sort,
+        _ => return None,
+    })
+}
+
+fn parse_op(s: &str) -> Option<(Op, usize)> {
+    const OPS: [(&str, Op); 7] = [
+        (">=", Op::GreaterEq),
+        ("<=", Op::LessEq),
+        ("!=", Op::NotEq),
+        (">", Op::Greater),
+        ("<", Op::Less),
+        ("=", Op::Eq),
+        (":", Op::Eq),
+    ];
+    OPS.iter()
+        .find(|(text, _)| s.starts_with(text))
+        .map(|(text, op)| (*op, text.len()))
+}
+
+fn parse_mode(s: &str) -> Option<i32> {
+    match s {
+        "osu" | "std" | "standard" | "0" => Some(0),
+        "taiko" | "1" => Some(1),
+        "fruits" | "catch" | "ctb" | "2" => Some(2),
+        "mania" | "3" => Some(3),
+        _ => None,
+    }
+}
+
+fn parse_sort_key(s: &str) -> Option<SortKey> {
+    Some(match s {
+        "artist" => SortKey::Artist,
+        "bpm" => SortKey::Bpm,
+        "creator" | "mapper" => SortKey::Creator,
+        "date" => SortKey::DateModified,
+        "length" => SortKey::Length,
+        "star" | "stars" | "difficulty" => SortKey::Stars,
+        "title" => SortKey::Title,
+        "ar" => SortKey::ApproachRate,
+        "cs" => SortKey::CircleSize,
+        "od" => SortKey::OverallDifficulty,
+        "hp" => SortKey::HpDrainRate,
+        _ => return None,
+    })
+}
+
+/// Accepts plain seconds (`90`, `90s`), minutes (`2m`) or `m:ss`.
+fn parse_length(s: &str) -> Option<f64> {
+    if let Some((m, sec)) = s.split_once(':') {
+        let m: f64 = m.parse().ok()?;
+        let sec: f64 = sec.parse().ok()?;
+        return Some(m * 60.0 + sec);
+    }
+    if let Some(m) = s.strip_suffix('m') {
+        return m.parse::<f64>().ok().map(|m| m * 60.0);
+    }
+    s.strip_suffix('s').unwrap_or(s).parse().ok()
+}
+
+/// Splits the query on whitespace, keeping quoted parts together. Returns
+/// each token with quotes removed and its byte offset.
+fn tokenize(input: &str) -> Result<Vec<(usize, String)>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut token = String::new();
+        let mut quote_start = None;
+        while let Some(&(i, c)) = chars.peek() {
+            if c == '"' {
+                quote_start = match quote_start {
+                    Some(_) => None,
+                    None => Some(i),
+                };
+            } else if c.is_whitespace() && quote_start.is_none() {
+                break;
+            } else {
+                token.push(c);
+            }
+            chars.next();
+        }
+        if let Some(position) = quote_start {
+            return Err(QueryError {
+                position,
+                message: "Unterminated quote".to_string(),
+            });
+        }
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Self, QueryError> {
+        let mut query = Query::default();
+        for (position, token) in tokenize(input)? {
+            let error = |message: String| QueryError { position, message };
+
+            let key_len = token
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(token.len());
+            let key = token[..key_len].to_ascii_lowercase();
+            let (Some(key), Some((op, op_len))) = (parse_key(&key), parse_op(&token[key_len..]))
+            else {
+                query.terms.push(token.to_lowercase());
+                continue;
+            };
+            let value = token[key_len + op_len..].to_lowercase();
+            if value.is_empty() {
+                return Err(error(format!("Missing value in '{token}'")));
+            }
+
+            let negate = match op {
+                Op::Eq => false,
+                Op::NotEq => true,
+                _ if matches!(key, Key::Number(_)) => false,
+                _ => return Err(error(format!("Only = and != can be used in '{token}'"))),
+            };
+            match key {
+                Key::Number(field) => {
+                    let parsed = match field {
+                        NumberField::Length => parse_length(&value),
+                        _ => value.parse().ok(),
+                    };
+                    let value =
+                        parsed.ok_or_else(|| error(format!("'{value}' is not a number")))?;
+                    query.filters.push(Filter::Number { field, op, value });
+                }
+                Key::Text(field) => query.filters.push(Filter::Text {
+                    field,
+                    negate,
+                    value,
+                }),
+                Key::Mode => {
+                    let mode = parse_mode(&value)
+                        .ok_or_else(|| error(format!("Unknown mode '{value}'")))?;
+                    query.filters.push(Filter::Mode { negate, mode });
+                }
+                Key::Sort => {
+                    if negate {
+                        return Err(error("Sort needs '=' or ':'".to_string()));
+                    }
+                    let (descending, name) = match value.strip_prefix('-') {
+                        Some(name) => (true, name),
+                        None => (false, value.as_str()),
+                    };
+                    let sort_key = parse_sort_key(name)
+                        .ok_or_else(|| error(format!("Unknown sort key '{name}'")))?;
+                    query.sort = Some((sort_key, descending));
+                }
+            }
+        }
+        Ok(query)
+    }
+
+    pub fn matches(&self, entry: &IndexEntry) -> bool {
+        let fields = [
+            &entry.title,
+            &entry.title_unicode,
+            &entry.artist,
+            &entry.artist_unicode,
+            &entry.creator,
+            &entry.version,
+            &entry.tags,
+            &entry.source,
+        ];
+        let text = fields
+            .iter()
+            .map(|f| f.to_lowercase())
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.terms.iter().all(|term| text.contains(term))
+            && self.filters.iter().all(|f| f.matches(entry))
+    }
+
+    /// Returns the matching entries, sorted if the query asks for it.
+    pub fn apply<'a>(&self, entries: &'a [IndexEntry]) -> Vec<&'a IndexEntry> {
+        let mut found: Vec<&IndexEntry> = entries.iter().filter(|e| self.matches(e)).collect();
+        if let Some((key, descending)) = self.sort {
+            found.sort_by(|a, b| {
+                let ord = key.compare(a, b);
+                if descending { ord.reverse() } else { ord }
+            });
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn entry(title: &str, title_unicode: &str) -> IndexEntry {
+        IndexEntry {
+            path: PathBuf::from(format!("{title}.osu")),
+            mtime: 0,
+            size: 0,
+            md5: String::new(),
+            sha256: String::new(),
+            title: title.to_string(),
+            title_unicode: title_unicode.to_string(),
+            artist: "xi".to_string(),
+            artist_unicode: String::new(),
+            creator: "Nakagawa-Kanon".to_string(),
+            version: "Insane".to_string(),
+            source: String::new(),
+            tags: String::new(),
+            beatmap_id: 0,
+            beatmap_set_id: 0,
+            audio_filename: "audio.mp3".to_string(),
+            mode: 0,
+            hp_drain_rate: 6.0,
+            circle_size: 4.0,
+            overall_difficulty: 8.0,
+            approach_rate: 9.0,
+            bpm_min: 200.0,
+            bpm_max: 200.0,
+            length_ms: 90_000,
+            circles: 0,
+            sliders: 0,
+            spinners: 0,
+            stars: 5.0,
+        }
+    }
+
+    fn titles(query: &str, entries: &[IndexEntry]) -> Vec<String> {
+        Query::parse(query)
+            .unwrap()
+            .apply(entries)
+            .iter()
+            .map(|e| e.title.clone())
+            .collect()
+    }
+
+    fn error_at(query: &str) -> usize {
+        Query::parse(query).unwrap_err().position
+    }
+
+    #[test]
+    fn number_filters() {
+        let hard = entry("hard", "");
+        let mut easy = entry("easy", "");
+        easy.approach_rate = 7.0;
+        easy.circle_size = 5.0;
+        easy.overall_difficulty = 5.0;
+        easy.stars = 5.5;
+        easy.bpm_min = 120.0;
+        easy.bpm_max = 180.0;
+        easy.length_ms = 89_000;
+        let entries = [hard, easy];
+
+        assert_eq!(titles("ar>9", &entries), Vec::<String>::new());
+        assert_eq!(titles("ar>=9", &entries), ["hard"]);
+        assert_eq!(titles("cs<=4", &entries), ["hard"]);
+        assert_eq!(titles("od=8", &entries), ["hard"]);
+        assert_eq!(titles("od!=8", &entries), ["easy"]);
+        assert_eq!(titles("stars>5.5", &entries), Vec::<String>::new());
+        assert_eq!(titles("stars=5.5", &entries), ["easy"]);
+        assert_eq!(titles("bpm>=180", &entries), ["hard", "easy"]);
+        assert_eq!(titles("bpm>180", &entries), ["hard"]);
+        assert_eq!(titles("length<1:30", &entries), ["easy"]);
+        assert_eq!(titles("length>=90s", &entries), ["hard"]);
+    }
+
+    #[test]
+    fn text_and_mode_filters() {
+        let mut taiko = entry("Blue Zenith", "");
+        taiko.mode = 1;
+        taiko.creator = "Asphyxia".to_string();
+        let entries = [taiko, entry("Freedom Dive", "")];
+
+        assert_eq!(titles("mode=taiko", &entries), ["Blue Zenith"]);
+        assert_eq!(titles("mode!=taiko", &entries), ["Freedom Dive"]);
+        assert_eq!(titles("creator=asphyxia", &entries), ["Blue Zenith"]);
+        assert_eq!(titles("mapper:kanon", &entries), ["Freedom Dive"]);
+        assert_eq!(titles("creator!=kanon", &entries), ["Blue Zenith"]);
+    }
+
+    #[test]
+    fn quoted_values_and_unicode_fields() {
+        let mut unicode = entry("Hoshi no Ame", "星の雨");
+        unicode.artist_unicode = "ゆよゆっぺ".to_string();
+        let entries = [unicode, entry("Freedom Dive", "")];
+
+        assert_eq!(titles("title=\"freedom dive\"", &entries), ["Freedom Dive"]);
+        assert_eq!(titles("\"dive xi\"", &entries), Vec::<String>::new());
+        assert_eq!(titles("\"freedom dive\" xi", &entries), ["Freedom Dive"]);
+        assert_eq!(titles("title=星", &entries), ["Hoshi no Ame"]);
+        assert_eq!(titles("artist=ゆよ", &entries), ["Hoshi no Ame"]);
+        assert_eq!(titles("雨", &entries), ["Hoshi no Ame"]);
+    }
+
+    #[test]
+    fn sorts_descending() {
+        let mut slow = entry("slow", "");
+        slow.bpm_max = 120.0;
+        let mut fast = entry("fast", "");
+        fast.bpm_max = 240.0;
+        let entries = [slow, entry("mid", ""), fast];
+
+        assert_eq!(titles("sort=-bpm", &entries), ["fast", "mid", "slow"]);
+        assert_eq!(titles("sort=bpm", &entries), ["slow", "mid", "fast"]);
+    }
+
+    #[test]
+    fn errors_point_at_the_token() {
+        assert_eq!(error_at("ar>9 title=\"blue"), 11);
+        assert_eq!(error_at("blue ar>"), 5);
+        assert_eq!(error_at("od=8 cs=big"), 5);
+        assert_eq!(error_at("mode=pong"), 0);
+        assert_eq!(error_at("x sort=-colour"), 2);
+        assert_eq!(error_at("sort!=bpm"), 0);
+        assert_eq!(error_at("ar>9  title<blue"), 6);
+    }
+}
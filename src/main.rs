@@ -2,16 +2,274 @@ extern crate bytemuck;
 extern crate gl;
 extern crate glfw;
 
+pub mod gameplay;
 pub mod graphics;
+pub mod library;
 pub mod resource;
+#[cfg(test)]
+mod test_util;
 
-use std::{collections::VecDeque, path::Path};
+use std::{collections::VecDeque, env, fs, path::Path, process, time::Duration};
 
 use glfw::{Action, Context, Key};
 
-use crate::{graphics::circle, resource::osufile::HitObjectType};
+use crate::{
+    gameplay::{
+        Clock, Mods, OffsetClock, Offsets,
+        calibration::{Calibration, MIN_TAPS},
+        countdown::{Countdown, lead_in_start_ms},
+        headless::HeadlessRunner,
+        input::Replay,
+        mods::difficulty_range,
+    },
+    graphics::circle,
+    library::BeatmapLibrary,
+    resource::{
+        audio::{
+            AudioPlayer, PlayerState,
+            analysis::detect_timing,
+            bus::Bus,
+            decode::decode_all,
+            hitsound::{HitsoundLibrary, SampleBank, SampleName},
+            loudness::TARGET_LUFS,
+            metronome::write_click_track,
+            mixdown::Mixdown,
+        },
+        osufile::HitObjectType,
+    },
+};
+
+/// Where hitsounds are looked up when the beatmap has no custom samples.
+const DEFAULT_SAMPLES_DIR: &str = "skin";
+/// Master volume change per Up/Down key press.
+const VOLUME_STEP: f32 = 0.1;
+/// How often loading progress is printed.
+const LOAD_POLL: Duration = Duration::from_millis(100);
+/// Library cache in the songs folder, unless `scan` is given another.
+const LIBRARY_FILE: &str = "library.json";
+/// Where the global and per-beatmap audio offsets are kept.
+const OFFSETS_FILE: &str = "offsets.json";
+/// Local offset change per +/- key press, in milliseconds.
+const LOCAL_OFFSET_STEP: f64 = 5.0;
+const CALIBRATION_BPM: f64 = 120.0;
+const CALIBRATION_BEATS: usize = 64;
+
+fn usage() -> ! {
+    eprintln!("Usage:");
+    eprintln!("  rusty_osu simulate <file.osu> [--mods HDDT] [--replay frames.txt] [--step ms]");
+    eprintln!("  rusty_osu scan <songs dir> [--cache library.json]");
+    eprintln!("  rusty_osu render <file.osu> <out.wav> [--mods DT] [--samples dir]");
+    eprintln!("  rusty_osu calibrate [--bpm 120 | --map file.osu]");
+    eprintln!("  rusty_osu timing <audio file>");
+    process::exit(2);
+}
+
+/// Runs a beatmap headlessly and prints the score summary as JSON.
+/// Without `--replay` the map is autoplayed.
+fn simulate(args: &[String]) {
+    let Some(path) = args.first() else { usage() };
+    let mut mods = Mods::empty();
+    let mut replay = None;
+    let mut step = gameplay::headless::DEFAULT_STEP_MS;
+
+    let mut opts = args[1..].iter();
+    while let Some(opt) = opts.next() {
+        let Some(value) = opts.next() else { usage() };
+        match opt.as_str() {
+            "--mods" => mods = Mods::from_acronyms(value).unwrap_or_else(|| usage()),
+            "--replay" => {
+                let data = fs::read_to_string(value).expect("Couldn't read the replay frames");
+                replay = Some(Replay::parse_frames(&data));
+            }
+            "--step" => match value.parse::<f64>() {
+                Ok(ms) if ms > 0.0 => step = ms,
+                _ => usage(),
+            },
+            _ => usage(),
+        }
+    }
+
+    let bm = resource::osufile::parse_osu(Path::new(path));
+    let input = replay.unwrap_or_else(|| {
+        mods |= Mods::AUTOPLAY;
+        Replay::autoplay(&bm)
+    });
+    let summary = HeadlessRunner::new(&bm, mods, input).with_step(step).run();
+    println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+}
+
+/// Updates the library index cache for a songs folder.
+fn scan(args: &[String]) {
+    let Some(songs) = args.first() else { usage() };
+    let cache = match &args[1..] {
+        [] => Path::new(songs).join(LIBRARY_FILE),
+        [opt, value] if opt == "--cache" => Path::new(value).to_path_buf(),
+        _ => usage(),
+    };
+
+    let mut library = BeatmapLibrary::load(&cache).expect("Couldn't read the library cache");
+    let stats = library
+        .scan(Path::new(songs))
+        .expect("Couldn't scan the songs folder");
+    library
+        .save(&cache)
+        .expect("Couldn't write the library cache");
+    println!(
+        "{} beatmaps: {} added, {} updated, {} unchanged, {} removed, {} failed, {} audio files measured",
+        library.entries().len(),
+        stats.added,
+        stats.updated,
+        stats.unchanged,
+        stats.removed,
+        stats.failed,
+        stats.measured
+    );
+}
+
+/// Renders a beatmap's music and hitsounds to a WAV file.
+fn render(args: &[String]) {
+    let [path, out, opts @ ..] = args else { usage() };
+    let mut mods = Mods::empty();
+    let mut samples = Path::new(DEFAULT_SAMPLES_DIR).to_path_buf();
+
+    let mut opts = opts.iter();
+    while let Some(opt) = opts.next() {
+        let Some(value) = opts.next() else { usage() };
+        match opt.as_str() {
+            "--mods" => mods = Mods::from_acronyms(value).unwrap_or_else(|| usage()),
+            "--samples" => samples = Path::new(value).to_path_buf(),
+            _ => usage(),
+        }
+    }
+
+    let path = Path::new(path);
+    let bm = resource::osufile::parse_osu(path);
+    let stats = Mixdown::new(&bm, path.parent().unwrap(), &samples)
+        .with_rate(mods.speed_multiplier(), mods.rate_mode())
+        .render(Path::new(out))
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            process::exit(1);
+        });
+    println!(
+        "Rendered {:.1}s with {} hitsounds to {out}",
+        stats.length_ms / 1000.0,
+        stats.hitsounds
+    );
+}
+
+/// Plays a metronome, times the taps along to it and saves the average
+/// error as the global audio offset.
+fn calibrate(args: &[String]) {
+    let mut bpm = CALIBRATION_BPM;
+    let mut opts = args.iter();
+    while let Some(opt) = opts.next() {
+        let Some(value) = opts.next() else { usage() };
+        match opt.as_str() {
+            "--bpm" => match value.parse::<f64>() {
+                Ok(b) if b > 0.0 => bpm = b,
+                _ => usage(),
+            },
+            "--map" => {
+                let bm = resource::osufile::parse_osu(Path::new(value));
+                bpm = bm
+                    .timing_points
+                    .iter()
+                    .find(|tp| tp.uninherited && tp.ms_per_beat > 0.0)
+                    .map(|tp| 60000.0 / tp.ms_per_beat)
+                    .unwrap_or_else(|| usage());
+            }
+            _ => usage(),
+        }
+    }
+
+    let track = env::temp_dir().join("rusty_osu_metronome.wav");
+    write_click_track(&track, bpm, CALIBRATION_BEATS).expect("Couldn't write the metronome track");
+
+    let mut glfw = glfw::init(glfw::fail_on_errors).unwrap();
+    let (mut window, events) = glfw
+        .create_window(640, 480, "Offset calibration", glfw::WindowMode::Windowed)
+        .expect("Failed to create GLFW window.");
+    window.set_key_polling(true);
+
+    let (mut player, _handle) = AudioPlayer::new_async(&track);
+    if let Err(e) = player.start() {
+        eprintln!("{e}");
+        return;
+    }
+    if let Err(e) = player.wait_loaded(Duration::MAX) {
+        eprintln!("{e}");
+        return;
+    }
+    player.play();
+    println!("Tap Z, X or Space on every click at {bpm:.0} BPM, Escape to finish.");
+
+    let mut calibration = Calibration::new(bpm, 60000.0 / bpm);
+    while !window.should_close() && player.is_playing() {
+        // Wake up for key presses straight away, so taps are timed closely.
+        glfw.wait_events_timeout(0.01);
+        for (_, event) in glfw::flush_messages(&events) {
+            match event {
+                glfw::WindowEvent::Key(Key::Escape, _, Action::Press, _) => window.set_should_close(true),
+                glfw::WindowEvent::Key(Key::Z | Key::X | Key::Space, _, Action::Press, _) => {
+                    if let Some(error) = calibration.tap(player.get_time_ms()) {
+                        println!("{error:+.0} ms");
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let Some(offset) = calibration.recommended_offset_ms() else {
+        println!("Only {} taps, at least {MIN_TAPS} are needed.", calibration.taps());
+        return;
+    };
+    let path = Path::new(OFFSETS_FILE);
+    let mut offsets = Offsets::load(path).expect("Couldn't read the offsets");
+    offsets.global_ms = offset;
+    offsets.save(path).expect("Couldn't save the offsets");
+    println!(
+        "Mean error {:+.1} ms over {} taps, global offset set to {offset:+.0} ms",
+        calibration.mean_error_ms().unwrap_or(0.0),
+        calibration.taps()
+    );
+}
+
+/// Detects the tempo of a track and prints the proposed uninherited timing
+/// points as `.osu` lines.
+fn timing(args: &[String]) {
+    let [path] = args else { usage() };
+    let audio = decode_all(Path::new(path)).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        process::exit(1);
+    });
+    let proposals = detect_timing(&audio);
+    if proposals.is_empty() {
+        println!("No steady beat found.");
+    }
+    for p in proposals {
+        let tp = &p.timing_point;
+        println!(
+            "{},{},{},{},{},{},1,{}  // {:.2} BPM, confidence {:.2}, meter confidence {:.2}",
+            tp.offset, tp.ms_per_beat, tp.meter, tp.sample_type, tp.sample_set, tp.volume, tp.effects,
+            60000.0 / tp.ms_per_beat, p.confidence, p.meter_confidence
+        );
+    }
+}
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("simulate") => return simulate(&args[1..]),
+        Some("scan") => return scan(&args[1..]),
+        Some("render") => return render(&args[1..]),
+        Some("calibrate") => return calibrate(&args[1..]),
+        Some("timing") => return timing(&args[1..]),
+        Some(_) => usage(),
+        None => {}
+    }
+
     let mut glfw = glfw::init(glfw::fail_on_errors).unwrap();
 
     let (mut window, events) = glfw
@@ -88,8 +346,8 @@ fn main() {
     
     let p = Path::new("test_res/UPLIFT SPICE - Omega Rhythm/UPLIFT SPICE - Omega Rhythm (Jemmmmy) [lightr's Insane].osu");
     let bm: resource::osufile::OsuFile = resource::osufile::parse_osu(&p);
-    let bmn = bm.metadata.title;
-    let bma = bm.metadata.artist;
+    let bmn = &bm.metadata.title;
+    let bma = &bm.metadata.artist;
     println!("Title: {bma} - {bmn}");
     let ar = bm.difficulty.approach_rate;
     let cs = bm.difficulty.circle_size;
@@ -99,13 +357,60 @@ fn main() {
     
     let scale: f32 = 54.4 - 4.48 * cs;
 
-    let p_aud = p.parent().unwrap().join(bm.general.audio_filename).as_path().to_owned();
+    let p_aud = p.parent().unwrap().join(&bm.general.audio_filename).as_path().to_owned();
     let p_aud_s = p_aud.to_str().unwrap();
     println!("Audio file: {p_aud_s}");
 
-    let (mut player, _handle) = resource::audio::AudioPlayer::new_async(&p_aud);
+    let offsets_path = Path::new(OFFSETS_FILE);
+    let mut offsets = Offsets::load(offsets_path).expect("Couldn't read the offsets");
 
-    player.start();
+    let (mut player, _handle) = AudioPlayer::new_async(&p_aud);
+    // Loudness is known once the songs folder has been scanned.
+    let songs_dir = p.parent().and_then(Path::parent).unwrap_or(Path::new("."));
+    if let Ok(library) = BeatmapLibrary::load(&songs_dir.join(LIBRARY_FILE))
+        && let Some(entry) = library.entries().iter().find(|e| e.md5 == bm.md5) {
+        player.set_normalization(library.normalization_gain(entry, TARGET_LUFS));
+    }
+
+    if let Err(e) = player.start() {
+        eprintln!("{e}");
+        return;
+    }
+    loop {
+        match player.wait_loaded(LOAD_POLL) {
+            Ok(true) => break,
+            Ok(false) => {
+                let percent = player.load_progress().fraction() * 100.0;
+                println!("Loading audio... {percent:.0}%");
+                glfw.poll_events();
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                return;
+            }
+        }
+    }
+    // How long before its time an object appears, from AR as the mods
+    // leave it. There's no mod selection here yet.
+    let mods = Mods::empty();
+    let preempt = difficulty_range(mods.apply(&bm.difficulty).approach_rate, 1800.0, 1200.0, 450.0);
+    let fado = 100;
+    let countdown = Countdown::new(&bm);
+
+    let default_bank = SampleBank::from_name(&bm.general.sample_set).unwrap_or(SampleBank::Normal);
+    let out_rate = player.output_rate().unwrap_or(44100);
+    let mut hitsounds = HitsoundLibrary::new(p.parent().unwrap(), Path::new(DEFAULT_SAMPLES_DIR), out_rate);
+    hitsounds.preload(&bm.hit_objects, &bm.timing_points, default_bank);
+    let mut next_hitsound = 0;
+    let countdown_beats = countdown.as_ref().map_or(&[][..], Countdown::beats);
+    for (_, beat) in countdown_beats {
+        hitsounds.get(&SampleName::Skin(beat.sample()));
+    }
+    let mut next_countdown = 0;
+
+    // Only once the samples are loaded, so the clock doesn't run on while
+    // they decode. It starts before the track for the lead-in and countdown.
+    player.seek(lead_in_start_ms(&bm, preempt, countdown.as_ref()));
     player.play();
 
     let mut queue = VecDeque::new();
@@ -117,11 +422,18 @@ fn main() {
     }
 
     while !window.should_close() {
-        let elapsed_ms = player.get_time_ms() as i32 - bm.general.audio_lead_in;
-        let preempt = 500;
-        let fado = 100;
+        if let PlayerState::Error(e) = player.state() {
+            eprintln!("{e}");
+            break;
+        }
+        // Hitsounds go out through the same output as the music, so they're
+        // timed on the track itself; only what the player sees and hits
+        // allows for the offset.
+        let track_ms = player.get_time_ms();
+        let offset_ms = offsets.total_ms(&bm.md5);
+        let elapsed_ms = OffsetClock::new(&player, offset_ms).time_ms() as i32;
 
-        while i < bm.hit_objects.len() && bm.hit_objects[i].time <= elapsed_ms + preempt {
+        while i < bm.hit_objects.len() && bm.hit_objects[i].time <= elapsed_ms + preempt as i32 {
             let ho = &bm.hit_objects[i];
             if ho.obj_type.contains(HitObjectType::NEW_COMBO) {
                 cbi += 1;
@@ -135,6 +447,19 @@ fn main() {
             i += 1;
         }
 
+        while next_hitsound < bm.hit_objects.len() && bm.hit_objects[next_hitsound].time as f64 <= track_ms {
+            let ho = &bm.hit_objects[next_hitsound];
+            hitsounds.play(&mut player, ho, &bm.timing_points, default_bank);
+            next_hitsound += 1;
+        }
+
+        while let Some(&(time, beat)) = countdown_beats.get(next_countdown) && time <= track_ms {
+            if let Some(sample) = hitsounds.get(&SampleName::Skin(beat.sample())) {
+                player.play_sample(sample, 1.0);
+            }
+            next_countdown += 1;
+        }
+
         while match queue.back() {Some(ho) => ho.0.time + fado < elapsed_ms, None => false} {
             queue.pop_back();
         }
@@ -169,6 +494,27 @@ fn main() {
         for (_, event) in glfw::flush_messages(&events) {
             match event {
                 glfw::WindowEvent::Key(Key::Escape, _, Action::Press, _) => window.set_should_close(true),
+                glfw::WindowEvent::Key(Key::Up, _, Action::Press | Action::Repeat, _) => {
+                    let volume = (player.volume(Bus::Master) + VOLUME_STEP).min(1.0);
+                    player.set_volume(Bus::Master, volume);
+                }
+                glfw::WindowEvent::Key(Key::Down, _, Action::Press | Action::Repeat, _) => {
+                    let volume = (player.volume(Bus::Master) - VOLUME_STEP).max(0.0);
+                    player.set_volume(Bus::Master, volume);
+                }
+                glfw::WindowEvent::Key(Key::M, _, Action::Press, _) => {
+                    let muted = player.is_muted(Bus::Music);
+                    player.set_muted(Bus::Music, !muted);
+                }
+                glfw::WindowEvent::Key(key @ (Key::Equal | Key::Minus), _, Action::Press | Action::Repeat, _) => {
+                    let step = if key == Key::Equal { LOCAL_OFFSET_STEP } else { -LOCAL_OFFSET_STEP };
+                    let local = offsets.local_ms(&bm.md5) + step;
+                    offsets.set_local_ms(&bm.md5, local);
+                    println!("Local offset: {local:+.0} ms");
+                    if let Err(e) = offsets.save(offsets_path) {
+                        eprintln!("Couldn't save the offsets: {e}");
+                    }
+                }
                 _ => {}
             }
         }
@@ -0,0 +1,423 @@
+use super::decode::DecodedAudio;
+use crate::resource::osufile::TimingPoint;
+
+/// Resolution of the onset envelope.
+const HOP_SECS: f64 = 0.003;
+/// Length of the moving average taken off the envelope, so only sudden
+/// rises in loudness count as onsets.
+const BASELINE_SECS: f64 = 0.1;
+/// Scale of the energy before taking its log. Higher values hear onsets in
+/// quieter passages, lower ones keep accents apart for the meter.
+const COMPRESSION: f32 = 100.0;
+const MIN_BPM: f64 = 60.0;
+const MAX_BPM: f64 = 240.0;
+/// Tempos an octave away from this are less likely, which settles whether
+/// a song is 90 or 180 BPM the way most maps would time it.
+const PREFERRED_BPM: f64 = 120.0;
+/// Tempo is estimated per stretch of this length to find tempo changes.
+const SEGMENT_SECS: f64 = 20.0;
+/// Relative tempo difference between stretches that counts as a change.
+const TEMPO_CHANGE: f64 = 0.02;
+/// How far, as a share of the beat, an onset may be from the grid and
+/// still be used to fit it.
+const ONSET_TOLERANCE: f64 = 0.1;
+/// BPMs this close to a whole number are rounded to it.
+const SNAP_BPM: f64 = 0.02;
+
+/// An uninherited timing point proposed from the audio.
+#[derive(Debug)]
+pub struct TimingProposal {
+    pub timing_point: TimingPoint,
+    /// How strongly onsets line up with the beat grid, from 0 to 1.
+    pub confidence: f64,
+    /// How clearly the accents mark out the meter, from 0 to 1.
+    pub meter_confidence: f64,
+}
+
+/// Proposes timing for a track: one point per stretch of steady tempo,
+/// each placed on a downbeat. The grid is fitted to onsets over the whole
+/// stretch, so the BPM is usually exact to a hundredth.
+///
+/// Tempo changes are only looked for every 20 seconds or so, so points
+/// after the first may need moving to where the change really is.
+pub fn detect_timing(audio: &DecodedAudio) -> Vec<TimingProposal> {
+    let rate = audio.spec.rate as f64;
+    let hop = (rate * HOP_SECS).round().max(1.0) as usize;
+    let env = onset_envelope(audio, hop);
+    let hop_ms = hop as f64 / rate * 1000.0;
+    let to_hops = |secs: f64| (secs * 1000.0 / hop_ms).round() as usize;
+
+    let mut proposals = Vec::new();
+    for (start, end) in steady_stretches(&env, to_hops(SEGMENT_SECS), hop_ms) {
+        let Some(period) = estimate_period(&env[start..end], hop_ms) else {
+            continue;
+        };
+        let Some(grid) = fit_grid(&env, start, end, period) else {
+            continue;
+        };
+        let (meter, downbeat, meter_confidence) = detect_meter(&env[..end], &grid);
+
+        let mut ms_per_beat = grid.period * hop_ms;
+        let bpm = 60000.0 / ms_per_beat;
+        if (bpm - bpm.round()).abs() < SNAP_BPM {
+            ms_per_beat = 60000.0 / bpm.round();
+        }
+        // The first downbeat in the stretch, or at the very start.
+        let bar = grid.period * meter as f64;
+        let mut first = grid.phase + downbeat as f64 * grid.period;
+        first -= ((first - start as f64) / bar).floor() * bar;
+        let offset = (first * hop_ms).max(0.0);
+
+        proposals.push(TimingProposal {
+            timing_point: TimingPoint {
+                offset: offset.round(),
+                ms_per_beat,
+                meter,
+                sample_type: 0,
+                sample_set: 0,
+                volume: 100,
+                uninherited: true,
+                effects: 0,
+            },
+            confidence: grid.confidence,
+            meter_confidence,
+        });
+    }
+    proposals
+}
+
+/// How suddenly the track gets louder at each hop, with the local average
+/// taken off so attacks stand out over sustained sound.
+fn onset_envelope(audio: &DecodedAudio, hop: usize) -> Vec<f32> {
+    let channels = audio.spec.channels.count();
+    let energies: Vec<f32> = audio
+        .samples
+        .chunks(hop * channels)
+        .map(|run| {
+            let mut energy = 0.0;
+            for frame in run.chunks_exact(channels) {
+                let mono = frame.iter().sum::<f32>() / channels as f32;
+                energy += mono * mono;
+            }
+            // Compressed, so quiet passages still show their onsets.
+            (1.0 + COMPRESSION * energy / hop as f32).ln()
+        })
+        .collect();
+
+    let flux: Vec<f32> = energies
+        .windows(2)
+        .map(|w| (w[1] - w[0]).max(0.0))
+        .collect();
+    let half = ((BASELINE_SECS / HOP_SECS) as usize / 2).max(1);
+    let mut env = vec![0.0; energies.len()];
+    for (i, value) in flux.iter().enumerate() {
+        let around = &flux[i.saturating_sub(half)..(i + half + 1).min(flux.len())];
+        let baseline = around.iter().sum::<f32>() / around.len() as f32;
+        // `flux[i]` is the rise into hop `i + 1`.
+        env[i + 1] = (value - baseline).max(0.0);
+    }
+    env
+}
+
+/// Splits the envelope into runs of segments with the same tempo, as hop
+/// ranges. Segments an octave apart count as the same tempo, since the
+/// estimate can flip between the two.
+fn steady_stretches(env: &[f32], segment: usize, hop_ms: f64) -> Vec<(usize, usize)> {
+    let mut stretches: Vec<(usize, usize, Option<f64>)> = Vec::new();
+    for start in (0..env.len()).step_by(segment.max(1)) {
+        let end = (start + segment).min(env.len());
+        let period = estimate_period(&env[start..end], hop_ms);
+        // Segments without a tempo of their own, like quiet intros and
+        // breaks, join the stretch before them.
+        match stretches.last_mut() {
+            Some(last)
+                if last
+                    .2
+                    .is_none_or(|p| period.is_none_or(|q| same_tempo(p, q))) =>
+            {
+                last.1 = end;
+                last.2 = last.2.or(period);
+            }
+            _ => stretches.push((start, end, period)),
+        }
+    }
+    stretches.into_iter().map(|(s, e, _)| (s, e)).collect()
+}
+
+fn same_tempo(a: f64, b: f64) -> bool {
+    [0.5, 1.0, 2.0]
+        .iter()
+        .any(|octave| (b / a / octave - 1.0).abs() < TEMPO_CHANGE)
+}
+
+/// The beat period in hops from the envelope's autocorrelation, weighted
+/// towards [`PREFERRED_BPM`]. `None` if nothing repeats, e.g. in silence.
+fn estimate_period(env: &[f32], hop_ms: f64) -> Option<f64> {
+    let min_lag = (60000.0 / MAX_BPM / hop_ms).floor() as usize;
+    let max_lag = ((60000.0 / MIN_BPM / hop_ms).ceil() as usize).min(env.len() / 2);
+    if min_lag < 2 || max_lag <= min_lag + 2 {
+        return None;
+    }
+
+    let correlation: Vec<f64> = (min_lag - 1..=max_lag + 1)
+        .map(|lag| {
+            let sum: f64 = env
+                .iter()
+                .zip(&env[lag..])
+                .map(|(a, b)| (a * b) as f64)
+                .sum();
+            sum / (env.len() - lag) as f64
+        })
+        .collect();
+    let weight = |lag: f64| {
+        let octaves = (60000.0 / (lag * hop_ms) / PREFERRED_BPM).log2();
+        (-0.5 * octaves * octaves).exp()
+    };
+
+    let (best, score) = (1..correlation.len() - 1)
+        .map(|i| (i, correlation[i] * weight((i + min_lag - 1) as f64)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    if score <= 0.0 {
+        return None;
+    }
+    // Parabolic interpolation between the neighbouring lags.
+    let (a, b, c) = (
+        correlation[best - 1],
+        correlation[best],
+        correlation[best + 1],
+    );
+    let denominator = a - 2.0 * b + c;
+    let shift = if denominator < 0.0 {
+        0.5 * (a - c) / denominator
+    } else {
+        0.0
+    };
+    Some((best + min_lag - 1) as f64 + shift.clamp(-0.5, 0.5))
+}
+
+/// A beat grid in hops: beat `k` falls at `phase + k * period`.
+struct Grid {
+    period: f64,
+    phase: f64,
+    confidence: f64,
+}
+
+/// Finds the grid phase that lines up with the most onsets between
+/// `start` and `end`, then fits period and phase to the onsets near it by
+/// least squares.
+fn fit_grid(env: &[f32], start: usize, end: usize, period: f64) -> Option<Grid> {
+    // Peaks are a hop or two wide, so look either side of each beat.
+    let at = |position: f64| -> f32 {
+        let i = position.round() as usize;
+        env[i.saturating_sub(1)..(i + 2).min(end)]
+            .iter()
+            .copied()
+            .fold(0.0, f32::max)
+    };
+    let comb = |phase: f64| -> f64 {
+        let beats = ((end - start) as f64 - phase) / period;
+        let sum: f64 = (0..beats.ceil() as usize)
+            .map(|k| at(start as f64 + phase + k as f64 * period) as f64)
+            .sum();
+        sum / beats.ceil().max(1.0)
+    };
+
+    let scores: Vec<f64> = (0..period.ceil() as usize)
+        .map(|phase| comb(phase as f64))
+        .collect();
+    let (best, &score) = scores
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))?;
+    if score <= 0.0 {
+        return None;
+    }
+    let mean = scores.iter().sum::<f64>() / scores.len() as f64;
+    let confidence = (1.0 - mean / score).clamp(0.0, 1.0);
+
+    // Strongest onset within tolerance of each beat, by its centroid.
+    let reach = (period * ONSET_TOLERANCE).max(1.0);
+    let mut points = Vec::new();
+    let mut k = 0;
+    loop {
+        let expected = start as f64 + best as f64 + k as f64 * period;
+        if expected >= end as f64 {
+            break;
+        }
+        let from = (expected - reach).max(0.0).round() as usize;
+        let to = ((expected + reach).round() as usize).min(end - 1);
+        let (peak, &value) = env[from..=to]
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap();
+        if value > 0.0 {
+            let peak = from + peak;
+            let around = peak.saturating_sub(1)..(peak + 2).min(env.len());
+            let weight: f32 = env[around.clone()].iter().sum();
+            let centroid: f32 = around.map(|i| i as f32 * env[i]).sum::<f32>() / weight;
+            points.push((k as f64, centroid as f64, value as f64));
+        }
+        k += 1;
+    }
+
+    // Weighted least squares for position = phase + k * period.
+    let total: f64 = points.iter().map(|p| p.2).sum();
+    if points.len() < 2 || total <= 0.0 {
+        return Some(Grid {
+            period,
+            phase: (start + best) as f64,
+            confidence,
+        });
+    }
+    let mean_k = points.iter().map(|p| p.0 * p.2).sum::<f64>() / total;
+    let mean_t = points.iter().map(|p| p.1 * p.2).sum::<f64>() / total;
+    let (mut covariance, mut variance) = (0.0, 0.0);
+    for (k, t, w) in &points {
+        covariance += w * (k - mean_k) * (t - mean_t);
+        variance += w * (k - mean_k) * (k - mean_k);
+    }
+    let fitted = if variance > 0.0 {
+        covariance / variance
+    } else {
+        period
+    };
+    Some(Grid {
+        period: fitted,
+        phase: mean_t - fitted * mean_k,
+        confidence,
+    })
+}
+
+/// Beats per bar, which beat of the grid is the first downbeat, and how
+/// clear that is, from which beats carry the strongest accents.
+fn detect_meter(env: &[f32], grid: &Grid) -> (i32, usize, f64) {
+    let strengths: Vec<f64> = (0..)
+        .map(|k| grid.phase + k as f64 * grid.period)
+        .take_while(|t| (t.round() as usize) < env.len())
+        .map(|t| {
+            let i = t.round() as usize;
+            env[i.saturating_sub(1)..(i + 2).min(env.len())]
+                .iter()
+                .copied()
+                .fold(0.0, f32::max) as f64
+        })
+        .collect();
+    let overall = strengths.iter().sum::<f64>() / strengths.len().max(1) as f64;
+    if overall <= 0.0 {
+        return (4, 0, 0.0);
+    }
+
+    // Compare how much louder the strongest position in the bar is than
+    // average, for each meter; four wins unless three is clearly better.
+    let accent = |meter: usize| -> (usize, f64) {
+        (0..meter)
+            .map(|j| {
+                let beats: Vec<f64> = strengths.iter().skip(j).step_by(meter).copied().collect();
+                let mean = beats.iter().sum::<f64>() / beats.len().max(1) as f64;
+                (j, mean / overall)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap()
+    };
+    let (four, four_accent) = accent(4);
+    let (three, three_accent) = accent(3);
+    let (meter, downbeat, contrast) = if three_accent > four_accent * 1.1 {
+        (3, three, three_accent)
+    } else {
+        (4, four, four_accent)
+    };
+    // A downbeat at twice the average or more is taken as certain.
+    (meter, downbeat, (contrast - 1.0).clamp(0.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use symphonia::core::audio::{Channels, SignalSpec};
+
+    use super::*;
+
+    const RATE: u32 = 44100;
+
+    /// A stereo click on every beat from `offset_ms`, with louder, higher
+    /// clicks on each downbeat. `noise` adds that much white noise.
+    fn click_track(bpm: f64, offset_ms: f64, meter: usize, secs: f64, noise: f32) -> DecodedAudio {
+        let frames = (secs * RATE as f64) as usize;
+        let mut mono = vec![0.0f32; frames];
+        let beat = 60.0 / bpm;
+        let click = (0.03 * RATE as f64) as usize;
+        let mut n = 0;
+        loop {
+            let start = ((offset_ms / 1000.0 + n as f64 * beat) * RATE as f64).round() as usize;
+            if start >= frames {
+                break;
+            }
+            let (amplitude, hz) = if n % meter == 0 {
+                (0.8, 1500.0)
+            } else {
+                (0.5, 1000.0)
+            };
+            for (i, sample) in mono[start..].iter_mut().take(click).enumerate() {
+                let t = i as f64 / RATE as f64;
+                *sample += (amplitude * (-t / 0.008).exp() * (2.0 * PI * hz * t).sin()) as f32;
+            }
+            n += 1;
+        }
+
+        // A fixed seed, so the noise is the same every run.
+        let mut seed = 0x2545_f491u32;
+        let samples = mono
+            .iter()
+            .flat_map(|&s| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                let s = s + noise * (seed as f32 / u32::MAX as f32 * 2.0 - 1.0);
+                [s, s]
+            })
+            .collect();
+        DecodedAudio {
+            spec: SignalSpec::new(RATE, Channels::FRONT_LEFT | Channels::FRONT_RIGHT),
+            samples,
+        }
+    }
+
+    fn assert_timing(audio: &DecodedAudio, bpm: f64, offset_ms: f64, meter: i32) {
+        let proposals = detect_timing(audio);
+        assert_eq!(proposals.len(), 1, "{proposals:?}");
+        let proposal = &proposals[0];
+        let tp = &proposal.timing_point;
+        assert!((tp.ms_per_beat - 60000.0 / bpm).abs() <= 0.01, "{tp:?}");
+        assert!((tp.offset - offset_ms).abs() <= 2.0, "{tp:?}");
+        assert_eq!(tp.meter, meter);
+        assert!(tp.uninherited);
+        assert!(proposal.confidence > 0.8, "{proposal:?}");
+        assert!(proposal.meter_confidence > 0.2, "{proposal:?}");
+    }
+
+    #[test]
+    fn finds_a_steady_four_four() {
+        assert_timing(&click_track(120.0, 250.0, 4, 16.0, 0.0), 120.0, 250.0, 4);
+    }
+
+    #[test]
+    fn finds_a_fast_three_four() {
+        assert_timing(&click_track(174.0, 37.0, 3, 16.0, 0.0), 174.0, 37.0, 3);
+    }
+
+    #[test]
+    fn finds_a_slow_beat_under_noise() {
+        assert_timing(&click_track(90.0, 1000.0, 4, 16.0, 0.05), 90.0, 1000.0, 4);
+    }
+
+    #[test]
+    fn proposes_nothing_for_silence() {
+        let silence = DecodedAudio {
+            spec: SignalSpec::new(RATE, Channels::FRONT_LEFT | Channels::FRONT_RIGHT),
+            samples: vec![0.0; RATE as usize * 2 * 10],
+        };
+        assert!(detect_timing(&silence).is_empty());
+    }
+}
@@ -0,0 +1,99 @@
+/// Shortest gain change, so even instant changes don't click.
+pub const MIN_RAMP_SECS: f64 = 0.01;
+
+/// Gain stages the output passes through. Music and effects are mixed
+/// separately, then both go through the master bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    Master,
+    Music,
+    Effects,
+}
+
+impl Bus {
+    pub(super) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A linear gain that ramps towards its target a frame at a time.
+pub(super) struct Gain {
+    current: f32,
+    /// Level set from the game, kept while muted.
+    level: f32,
+    muted: bool,
+    step: f32,
+    remaining: usize,
+}
+
+impl Gain {
+    pub fn new() -> Self {
+        Self {
+            current: 1.0,
+            level: 1.0,
+            muted: false,
+            step: 0.0,
+            remaining: 0,
+        }
+    }
+
+    /// Moves to `level` over `frames` output frames.
+    pub fn set(&mut self, level: f32, frames: usize) {
+        self.level = level;
+        self.retarget(frames);
+    }
+
+    pub fn set_muted(&mut self, muted: bool, frames: usize) {
+        self.muted = muted;
+        self.retarget(frames);
+    }
+
+    fn retarget(&mut self, frames: usize) {
+        let target = if self.muted { 0.0 } else { self.level };
+        self.remaining = frames.max(1);
+        self.step = (target - self.current) / self.remaining as f32;
+    }
+
+    /// Gain for the next frame.
+    pub fn next(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.current = if self.remaining == 0 {
+                if self.muted { 0.0 } else { self.level }
+            } else {
+                self.current + self.step
+            };
+        }
+        self.current
+    }
+
+    /// Jumps straight to `level`, for starting a fade from silence.
+    pub fn jump(&mut self, level: f32) {
+        self.current = level;
+        self.remaining = 0;
+    }
+
+    /// Moves the ramp on by `frames` without producing gains.
+    pub fn skip(&mut self, frames: usize) {
+        if frames < self.remaining {
+            self.remaining -= frames;
+            self.current += self.step * frames as f32;
+        } else if self.remaining > 0 {
+            self.remaining = 1;
+            self.next();
+        }
+    }
+
+    /// Scales interleaved `output` frame by frame.
+    pub fn apply(&mut self, output: &mut [f32], channels: usize) {
+        if self.remaining == 0 && self.current == 1.0 {
+            return;
+        }
+        for frame in output.chunks_mut(channels) {
+            let gain = self.next();
+            for sample in frame {
+                *sample *= gain;
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    hint,
+    sync::atomic::{AtomicU64, Ordering, fence},
+    time::{Duration, Instant},
+};
+
+/// Tracks how far the device has got through the track, so the time comes
+/// from the samples actually consumed rather than from a wall clock.
+#[derive(Clone, Copy, Default)]
+pub(super) struct PlaybackClock {
+    /// Track position in seconds at the start of the most recent callback.
+    pub position_before: f64,
+    /// Track time covered by the most recent callback.
+    pub callback_span: f64,
+    /// Wall time the most recent callback's output lasts.
+    pub callback_length: f64,
+    /// When the most recent callback ran, if the player was playing to a
+    /// live output.
+    pub callback_at: Option<Instant>,
+    /// Delay between a callback and its first frame reaching the speakers.
+    pub latency: Duration,
+    /// Playback rate, to turn the latency into track time.
+    pub rate: f64,
+    /// The last seek the callback has carried out.
+    pub epoch: u64,
+}
+
+impl PlaybackClock {
+    pub fn time_ms(&self) -> f64 {
+        let mut secs = self.position_before;
+        match self.callback_at {
+            // Interpolate between callbacks, but never past what was written.
+            Some(at) if self.callback_length > 0.0 => {
+                let progress = at.elapsed().as_secs_f64() / self.callback_length;
+                secs += self.callback_span * progress.min(1.0);
+            }
+            _ => secs += self.callback_span,
+        }
+        // Negative during a lead-in, and until the first audio is heard.
+        (secs - self.latency.as_secs_f64() * self.rate) * 1000.0
+    }
+
+    pub fn reset(&mut self, position: f64) {
+        self.position_before = position;
+        self.callback_span = 0.0;
+        self.callback_at = None;
+        // Nothing from the new position is queued on the device yet.
+        self.latency = Duration::ZERO;
+    }
+}
+
+/// Publishes the callback's clock to other threads as a seqlock: the
+/// callback never waits, and readers retry if they catch it mid-update.
+pub(super) struct SharedClock {
+    seq: AtomicU64,
+    words: [AtomicU64; 7],
+    /// Reference point for storing `callback_at` as a number.
+    base: Instant,
+}
+
+impl SharedClock {
+    pub fn new() -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            words: Default::default(),
+            base: Instant::now(),
+        }
+    }
+
+    pub fn store(&self, clock: &PlaybackClock) {
+        let callback_at = clock
+            .callback_at
+            .map_or(0, |at| at.duration_since(self.base).as_nanos() as u64 + 1);
+        let words = [
+            clock.position_before.to_bits(),
+            clock.callback_span.to_bits(),
+            clock.callback_length.to_bits(),
+            callback_at,
+            clock.latency.as_nanos() as u64,
+            clock.rate.to_bits(),
+            clock.epoch,
+        ];
+
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        for (word, value) in self.words.iter().zip(words) {
+            word.store(value, Ordering::Relaxed);
+        }
+        self.seq.store(seq + 2, Ordering::Release);
+    }
+
+    pub fn load(&self) -> PlaybackClock {
+        let words = loop {
+            let before = self.seq.load(Ordering::Acquire);
+            let words = self.words.each_ref().map(|w| w.load(Ordering::Relaxed));
+            fence(Ordering::Acquire);
+            if before.is_multiple_of(2) && self.seq.load(Ordering::Relaxed) == before {
+                break words;
+            }
+            hint::spin_loop();
+        };
+
+        PlaybackClock {
+            position_before: f64::from_bits(words[0]),
+            callback_span: f64::from_bits(words[1]),
+            callback_length: f64::from_bits(words[2]),
+            callback_at: words[3]
+                .checked_sub(1)
+                .map(|nanos| self.base + Duration::from_nanos(nanos)),
+            latency: Duration::from_nanos(words[4]),
+            rate: f64::from_bits(words[5]),
+            epoch: words[6],
+        }
+    }
+}
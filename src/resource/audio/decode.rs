@@ -0,0 +1,293 @@
+use std::{error::Error, fmt, fs::File, io, path::Path};
+
+use symphonia::{
+    core::{
+        audio::{SampleBuffer, SignalSpec},
+        codecs::{Decoder, DecoderOptions},
+        errors::Error as SymphoniaError,
+        formats::{FormatReader, SeekMode, SeekTo},
+        io::MediaSourceStream,
+        probe::Hint,
+        units::{Time, TimeBase},
+    },
+    default::{get_codecs, get_probe},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    Open(io::ErrorKind),
+    /// The file isn't in a container format that can be read.
+    UnsupportedFormat,
+    NoTrack,
+    /// The track's codec can't be decoded.
+    UnsupportedCodec(String),
+    /// Reading the file failed partway through.
+    Stream(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Open(kind) => write!(f, "Cannot open audio file: {kind}"),
+            DecodeError::UnsupportedFormat => write!(f, "Unsupported audio format"),
+            DecodeError::NoTrack => write!(f, "No audio track in file"),
+            DecodeError::UnsupportedCodec(e) => write!(f, "Unsupported audio codec: {e}"),
+            DecodeError::Stream(e) => write!(f, "Audio decode error: {e}"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// One decoded packet as interleaved `f32` samples.
+pub struct Chunk<'a> {
+    /// Track frame of the first sample.
+    pub start_frame: u64,
+    pub spec: SignalSpec,
+    pub samples: &'a [f32],
+}
+
+/// A whole track decoded into memory.
+pub struct DecodedAudio {
+    pub spec: SignalSpec,
+    pub samples: Vec<f32>,
+}
+
+impl DecodedAudio {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.spec.channels.count()
+    }
+
+    pub fn duration_ms(&self) -> f64 {
+        self.frames() as f64 / self.spec.rate as f64 * 1000.0
+    }
+}
+
+/// Decodes the default track of an audio file packet by packet.
+pub struct TrackDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    time_base: Option<TimeBase>,
+    sample_rate: Option<u32>,
+    total_frames: Option<u64>,
+    /// Layout of the last decoded packet, or the track's if none yet.
+    spec: Option<SignalSpec>,
+    next_frame: u64,
+    buf: Option<SampleBuffer<f32>>,
+    silence: Vec<f32>,
+    skipped: u32,
+    /// Start frame of a chunk `skip_to` decoded but hasn't handed out yet,
+    /// and whether it was silence.
+    held: Option<(u64, bool)>,
+}
+
+impl TrackDecoder {
+    pub fn open(path: &Path) -> Result<Self, DecodeError> {
+        let file = File::open(path).map_err(|e| DecodeError::Open(e.kind()))?;
+        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(ext);
+        }
+        let probed = get_probe()
+            .format(&hint, mss, &Default::default(), &Default::default())
+            .map_err(|e| match e {
+                SymphoniaError::IoError(e) => DecodeError::Open(e.kind()),
+                _ => DecodeError::UnsupportedFormat,
+            })?;
+        let format = probed.format;
+        let track = format.default_track().ok_or(DecodeError::NoTrack)?;
+        let track_id = track.id;
+        let params = track.codec_params.clone();
+        let decoder = get_codecs()
+            .make(&params, &DecoderOptions::default())
+            .map_err(|e| DecodeError::UnsupportedCodec(e.to_string()))?;
+
+        let spec = match (params.sample_rate, params.channels) {
+            (Some(rate), Some(channels)) => Some(SignalSpec::new(rate, channels)),
+            _ => None,
+        };
+        Ok(Self {
+            format,
+            decoder,
+            track_id,
+            time_base: params.time_base,
+            sample_rate: params.sample_rate,
+            total_frames: params.n_frames,
+            spec,
+            next_frame: 0,
+            buf: None,
+            silence: Vec::new(),
+            skipped: 0,
+            held: None,
+        })
+    }
+
+    /// Length of the track in frames, if the container says.
+    pub fn total_frames(&self) -> Option<u64> {
+        self.total_frames
+    }
+
+    /// Packets that were corrupt and played as silence instead.
+    pub fn skipped_packets(&self) -> u32 {
+        self.skipped
+    }
+
+    /// Decodes the next packet of the track, or returns `None` at the end.
+    /// A corrupt packet comes out as silence of the same length, so the
+    /// rest of the track stays in time.
+    pub fn next_chunk(&mut self) -> Result<Option<Chunk<'_>>, DecodeError> {
+        let next = match self.held.take() {
+            Some(held) => Some(held),
+            None => self.decode_next()?,
+        };
+        let Some((start_frame, silent)) = next else {
+            return Ok(None);
+        };
+        // Only set once a packet has been decoded.
+        let spec = self.spec.unwrap();
+        let samples = match (silent, &self.buf) {
+            (false, Some(buf)) => buf.samples(),
+            _ => &self.silence,
+        };
+        Ok(Some(Chunk {
+            start_frame,
+            spec,
+            samples,
+        }))
+    }
+
+    /// Decodes and throws away packets until reaching `secs`, for formats
+    /// that can't [`seek`](Self::seek). Like a seek, the chunks that follow
+    /// start at or just before `secs`.
+    pub fn skip_to(&mut self, secs: f64) -> Result<(), DecodeError> {
+        loop {
+            let next = match self.held.take() {
+                Some(held) => Some(held),
+                None => self.decode_next()?,
+            };
+            let Some(next) = next else {
+                return Ok(());
+            };
+            let rate = self.spec.unwrap().rate;
+            if self.next_frame > (secs.max(0.0) * rate as f64) as u64 {
+                self.held = Some(next);
+                return Ok(());
+            }
+        }
+    }
+
+    /// Decodes the next packet into `buf`, or `silence` if it's corrupt.
+    /// Returns its start frame and whether it was silence.
+    fn decode_next(&mut self) -> Result<Option<(u64, bool)>, DecodeError> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(None);
+                }
+                // The track list changed, as in a chained stream; treat it as the end.
+                Err(SymphoniaError::ResetRequired) => return Ok(None),
+                Err(e) => return Err(DecodeError::Stream(e.to_string())),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let start_frame = self.next_frame;
+            match self.decoder.decode(&packet) {
+                Ok(decoded) => {
+                    let spec = *decoded.spec();
+                    let needed = decoded.capacity() * spec.channels.count();
+                    if self.buf.as_ref().is_none_or(|buf| buf.capacity() < needed) {
+                        self.buf = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+                    }
+                    let buf = self.buf.as_mut().unwrap();
+                    buf.copy_interleaved_ref(decoded);
+
+                    self.spec = Some(spec);
+                    self.next_frame += (buf.len() / spec.channels.count()) as u64;
+                    return Ok(Some((start_frame, false)));
+                }
+                Err(SymphoniaError::DecodeError(_) | SymphoniaError::IoError(_)) => {
+                    self.skipped += 1;
+                    let Some(spec) = self.spec else {
+                        continue;
+                    };
+                    let len = packet.dur as usize * spec.channels.count();
+                    self.silence.clear();
+                    self.silence.resize(len, 0.0);
+                    self.next_frame += packet.dur;
+                    return Ok(Some((start_frame, true)));
+                }
+                Err(e) => return Err(DecodeError::Stream(e.to_string())),
+            }
+        }
+    }
+
+    /// Seeks so that the chunks that follow start at or just before `secs`.
+    /// Returns `false` if the format can't seek.
+    pub fn seek(&mut self, secs: f64) -> bool {
+        let to = SeekTo::Time {
+            time: Time::from(secs.max(0.0)),
+            track_id: Some(self.track_id),
+        };
+        let Ok(seeked) = self.format.seek(SeekMode::Accurate, to) else {
+            return false;
+        };
+        self.decoder.reset();
+        self.held = None;
+        self.next_frame = match (self.time_base, self.sample_rate) {
+            (Some(tb), Some(rate)) => {
+                let time = tb.calc_time(seeked.actual_ts);
+                ((time.seconds as f64 + time.frac) * rate as f64).round() as u64
+            }
+            _ => seeked.actual_ts,
+        };
+        true
+    }
+}
+
+/// Decodes a whole file into memory, for offline work like analysis and
+/// rendering rather than playback.
+pub fn decode_all(path: &Path) -> Result<DecodedAudio, DecodeError> {
+    let mut decoder = TrackDecoder::open(path)?;
+    let mut spec = None;
+    let mut samples = Vec::new();
+    while let Some(chunk) = decoder.next_chunk()? {
+        spec.get_or_insert(chunk.spec);
+        samples.extend_from_slice(chunk.samples);
+    }
+    Ok(DecodedAudio {
+        spec: spec.ok_or(DecodeError::NoTrack)?,
+        samples,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{TempDir, write_wav};
+
+    #[test]
+    fn skip_to_lands_on_the_target() {
+        let dir = TempDir::new("decode_skip");
+        let path = dir.join("ramp.wav");
+        // Each frame holds its own index, so chunks show where they start.
+        let samples: Vec<f32> = (0..8000).map(|i| i as f32 / 32768.0).collect();
+        write_wav(&path, 8000, 1, &samples);
+
+        let mut decoder = TrackDecoder::open(&path).unwrap();
+        decoder.skip_to(0.5).unwrap();
+        let chunk = decoder.next_chunk().unwrap().unwrap();
+        let end = chunk.start_frame + chunk.samples.len() as u64;
+        assert!(chunk.start_frame <= 4000 && end > 4000);
+        let first = (chunk.samples[0] * 32768.0).round() as u64;
+        assert_eq!(first, chunk.start_frame);
+
+        // Skipping past the end leaves nothing to decode.
+        decoder.skip_to(2.0).unwrap();
+        assert!(decoder.next_chunk().unwrap().is_none());
+    }
+}
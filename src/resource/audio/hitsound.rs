@@ -0,0 +1,261 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use super::{AudioPlayer, decode::decode_all, mixer::Sample};
+use crate::resource::osufile::{HitObject, TimingPoint};
+
+/// Extensions tried for each sample, in order.
+const EXTENSIONS: [&str; 2] = ["wav", "ogg"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SampleBank {
+    Normal,
+    Soft,
+    Drum,
+}
+
+impl SampleBank {
+    /// Bank from its number in timing points and hit samples. Zero means
+    /// "inherit", which has no bank of its own.
+    pub fn from_index(index: i32) -> Option<Self> {
+        match index {
+            1 => Some(SampleBank::Normal),
+            2 => Some(SampleBank::Soft),
+            3 => Some(SampleBank::Drum),
+            _ => None,
+        }
+    }
+
+    /// Bank from the `SampleSet` name in the `[General]` section.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "normal" => Some(SampleBank::Normal),
+            "soft" => Some(SampleBank::Soft),
+            "drum" => Some(SampleBank::Drum),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SampleBank::Normal => "normal",
+            SampleBank::Soft => "soft",
+            SampleBank::Drum => "drum",
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// The `hitSound` field of a hit object.
+    #[derive(Debug, Clone, Copy)]
+    pub struct HitSound: i32 {
+        const NORMAL = 1;
+        const WHISTLE = 2;
+        const FINISH = 4;
+        const CLAP = 8;
+    }
+}
+
+/// A hit object's own sample settings, the `normalSet:additionSet:index:
+/// volume:filename` field. Zero fields fall back to the timing point.
+#[derive(Debug, Default)]
+pub struct HitSample {
+    pub normal_set: i32,
+    pub addition_set: i32,
+    pub index: i32,
+    pub volume: i32,
+    /// Plays this file from the beatmap folder instead of the bank samples.
+    pub filename: String,
+}
+
+impl HitSample {
+    pub fn parse(field: &str) -> Self {
+        let parts: Vec<&str> = field.split(':').collect();
+        let number = |i: usize| parts.get(i).and_then(|p| p.parse().ok()).unwrap_or(0);
+        Self {
+            normal_set: number(0),
+            addition_set: number(1),
+            index: number(2),
+            volume: number(3),
+            filename: parts.get(4).unwrap_or(&"").to_string(),
+        }
+    }
+
+    /// Reads the sample settings from the end of a hit object's extras.
+    pub fn of(object: &HitObject) -> Self {
+        match object.extras.rsplit(',').next() {
+            Some(field) if field.contains(':') => Self::parse(field),
+            _ => Self::default(),
+        }
+    }
+}
+
+/// Which sample file a hit plays.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SampleName {
+    /// A bank sample such as `soft-hitclap`. Index 0 uses the default
+    /// samples; other indices look for a custom sample in the beatmap
+    /// folder first (`soft-hitclap.wav` for 1, `soft-hitclap3.wav` for 3).
+    Bank {
+        bank: SampleBank,
+        sound: &'static str,
+        index: i32,
+    },
+    /// A file in the beatmap folder named by the hit object.
+    File(String),
+    /// A skin sample not tied to a hit, such as the countdown's `count3s`.
+    /// The beatmap folder can override it like any skin element.
+    Skin(&'static str),
+}
+
+/// Works out which samples a hit object plays and how loud, from its own
+/// settings and the timing point in effect at its time.
+pub fn resolve(
+    object: &HitObject,
+    timing_points: &[TimingPoint],
+    default_bank: SampleBank,
+) -> Vec<(SampleName, f32)> {
+    let timing = timing_points
+        .iter()
+        .rev()
+        .find(|tp| tp.offset <= object.time as f64)
+        .or(timing_points.first());
+    let hit_sample = HitSample::of(object);
+
+    let volume = match (hit_sample.volume, timing) {
+        (0, Some(tp)) => tp.volume,
+        (0, None) => 100,
+        (v, _) => v,
+    };
+    let volume = volume.clamp(0, 100) as f32 / 100.0;
+
+    if !hit_sample.filename.is_empty() {
+        return vec![(SampleName::File(hit_sample.filename), volume)];
+    }
+
+    // In timing points `sample_type` holds the bank and `sample_set` the
+    // custom sample index.
+    let timing_bank = timing.and_then(|tp| SampleBank::from_index(tp.sample_type));
+    let normal_bank = SampleBank::from_index(hit_sample.normal_set)
+        .or(timing_bank)
+        .unwrap_or(default_bank);
+    let addition_bank = SampleBank::from_index(hit_sample.addition_set).unwrap_or(normal_bank);
+    let index = match hit_sample.index {
+        0 => timing.map_or(0, |tp| tp.sample_set),
+        i => i,
+    };
+
+    let flags = HitSound::from_bits_truncate(object.hit_sound);
+    let mut sounds = vec![(normal_bank, "hitnormal")];
+    for (flag, sound) in [
+        (HitSound::WHISTLE, "hitwhistle"),
+        (HitSound::FINISH, "hitfinish"),
+        (HitSound::CLAP, "hitclap"),
+    ] {
+        if flags.contains(flag) {
+            sounds.push((addition_bank, sound));
+        }
+    }
+
+    sounds
+        .into_iter()
+        .map(|(bank, sound)| (SampleName::Bank { bank, sound, index }, volume))
+        .collect()
+}
+
+/// Loads hitsound samples on first use and keeps them for playback.
+///
+/// Custom samples come from the beatmap folder and everything else from
+/// `default_dir`. Samples that can't be found or decoded are silent.
+pub struct HitsoundLibrary {
+    beatmap_dir: PathBuf,
+    default_dir: PathBuf,
+    out_rate: u32,
+    samples: HashMap<SampleName, Option<Arc<Sample>>>,
+}
+
+impl HitsoundLibrary {
+    pub fn new(beatmap_dir: &Path, default_dir: &Path, out_rate: u32) -> Self {
+        Self {
+            beatmap_dir: beatmap_dir.to_path_buf(),
+            default_dir: default_dir.to_path_buf(),
+            out_rate,
+            samples: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, name: &SampleName) -> Option<Arc<Sample>> {
+        if let Some(sample) = self.samples.get(name) {
+            return sample.clone();
+        }
+        let sample = self.find(name).and_then(|path| match decode_all(&path) {
+            Ok(audio) => Some(Arc::new(Sample::new(&audio, self.out_rate))),
+            Err(e) => {
+                eprintln!("Couldn't load {}: {e}", path.display());
+                None
+            }
+        });
+        self.samples.insert(name.clone(), sample.clone());
+        sample
+    }
+
+    /// Loads every sample the hit objects use, so nothing is decoded
+    /// during play.
+    pub fn preload(
+        &mut self,
+        objects: &[HitObject],
+        timing_points: &[TimingPoint],
+        default_bank: SampleBank,
+    ) {
+        for object in objects {
+            for (name, _) in resolve(object, timing_points, default_bank) {
+                self.get(&name);
+            }
+        }
+    }
+
+    /// Plays the hitsounds of `object` through the player's mixer.
+    pub fn play(
+        &mut self,
+        player: &mut AudioPlayer,
+        object: &HitObject,
+        timing_points: &[TimingPoint],
+        default_bank: SampleBank,
+    ) {
+        for (name, volume) in resolve(object, timing_points, default_bank) {
+            if let Some(sample) = self.get(&name) {
+                player.play_sample(sample, volume);
+            }
+        }
+    }
+
+    fn find(&self, name: &SampleName) -> Option<PathBuf> {
+        match name {
+            SampleName::File(file) => {
+                let path = self.beatmap_dir.join(file);
+                path.is_file().then_some(path)
+            }
+            SampleName::Bank { bank, sound, index } => {
+                let base = format!("{}-{sound}", bank.name());
+                let custom = match index {
+                    0 => None,
+                    1 => find_with_extension(&self.beatmap_dir, &base),
+                    i => find_with_extension(&self.beatmap_dir, &format!("{base}{i}")),
+                };
+                custom.or_else(|| find_with_extension(&self.default_dir, &base))
+            }
+            SampleName::Skin(stem) => find_with_extension(&self.beatmap_dir, stem)
+                .or_else(|| find_with_extension(&self.default_dir, stem)),
+        }
+    }
+}
+
+fn find_with_extension(dir: &Path, stem: &str) -> Option<PathBuf> {
+    EXTENSIONS
+        .iter()
+        .map(|ext| dir.join(format!("{stem}.{ext}")))
+        .find(|path| path.is_file())
+}
@@ -0,0 +1,267 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+use symphonia::core::audio::Channels;
+
+use super::decode::DecodedAudio;
+
+/// Level tracks are normalized to, leaving headroom for hitsounds.
+pub const TARGET_LUFS: f64 = -16.0;
+/// Highest true peak normalization may raise a track to.
+const PEAK_CEILING_DBTP: f64 = -1.0;
+/// Gating block length and the step between blocks, per BS.1770.
+const BLOCK_SECS: f64 = 0.4;
+const STEP_SECS: f64 = 0.1;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+/// Taps of each phase of the true peak interpolation filter.
+const PHASE_TAPS: usize = 12;
+
+/// Loudness of a track as measured by ITU-R BS.1770 / EBU R128.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS.
+    pub integrated_lufs: f64,
+    /// Highest inter-sample peak in dBTP.
+    pub true_peak_dbtp: f64,
+}
+
+impl Loudness {
+    /// `None` for a track that is silent throughout.
+    pub fn measure(audio: &DecodedAudio) -> Option<Self> {
+        Some(Self {
+            integrated_lufs: integrated_loudness(audio)?,
+            true_peak_dbtp: true_peak(audio),
+        })
+    }
+
+    /// Linear gain that brings the track to `target_lufs`, turned down if
+    /// that would push its peaks over -1 dBTP.
+    pub fn gain(&self, target_lufs: f64) -> f32 {
+        let db = (target_lufs - self.integrated_lufs).min(PEAK_CEILING_DBTP - self.true_peak_dbtp);
+        10f64.powf(db / 20.0) as f32
+    }
+}
+
+/// A biquad filter in direct form I.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// The K-weighting of BS.1770: a high shelf for the head's effect, then a
+/// high pass, with coefficients worked out for any sample rate.
+fn k_weighting(rate: u32) -> [Biquad; 2] {
+    let rate = rate as f64;
+    let new = |b, a| Biquad {
+        b,
+        a,
+        x: [0.0; 2],
+        y: [0.0; 2],
+    };
+
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = new(
+        [1.0, -2.0, 1.0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+    [shelf, high_pass]
+}
+
+/// How much each channel counts: surrounds more, the LFE not at all.
+fn channel_weights(channels: Channels) -> Vec<f64> {
+    let surround =
+        Channels::SIDE_LEFT | Channels::SIDE_RIGHT | Channels::REAR_LEFT | Channels::REAR_RIGHT;
+    channels
+        .iter()
+        .map(|c| {
+            if c == Channels::LFE1 {
+                0.0
+            } else if surround.contains(c) {
+                1.41
+            } else {
+                1.0
+            }
+        })
+        .collect()
+}
+
+fn power_to_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// Gated integrated loudness over 400 ms blocks overlapping by 75%.
+fn integrated_loudness(audio: &DecodedAudio) -> Option<f64> {
+    let channels = audio.spec.channels.count();
+    let weights = channel_weights(audio.spec.channels);
+    let mut filters: Vec<[Biquad; 2]> = (0..channels)
+        .map(|_| k_weighting(audio.spec.rate))
+        .collect();
+
+    // Weighted mean square of each step; blocks are runs of these.
+    let step = ((audio.spec.rate as f64 * STEP_SECS) as usize).max(1);
+    let steps: Vec<f64> = audio
+        .samples
+        .chunks_exact(step * channels)
+        .map(|run| {
+            let mut sum = 0.0;
+            for frame in run.chunks_exact(channels) {
+                for ((&sample, [shelf, high_pass]), weight) in
+                    frame.iter().zip(&mut filters).zip(&weights)
+                {
+                    let y = high_pass.process(shelf.process(sample as f64));
+                    sum += weight * y * y;
+                }
+            }
+            sum / step as f64
+        })
+        .collect();
+    let per_block = (BLOCK_SECS / STEP_SECS).round() as usize;
+    let blocks: Vec<f64> = steps
+        .windows(per_block)
+        .map(|w| w.iter().sum::<f64>() / per_block as f64)
+        .filter(|&power| power_to_lufs(power) > ABSOLUTE_GATE_LUFS)
+        .collect();
+    if blocks.is_empty() {
+        return None;
+    }
+
+    let mean = |blocks: &mut dyn Iterator<Item = f64>| {
+        let (sum, count) = blocks.fold((0.0, 0), |(s, n), p| (s + p, n + 1));
+        sum / count.max(1) as f64
+    };
+    let gate = power_to_lufs(mean(&mut blocks.iter().copied())) + RELATIVE_GATE_LU;
+    Some(power_to_lufs(mean(
+        &mut blocks.iter().copied().filter(|&p| power_to_lufs(p) > gate),
+    )))
+}
+
+/// Highest peak of the signal upsampled to at least 176.4 kHz, which
+/// catches the peaks between samples that a DAC would reconstruct.
+fn true_peak(audio: &DecodedAudio) -> f64 {
+    let channels = audio.spec.channels.count();
+    let factor = (176400 / audio.spec.rate.max(1)).clamp(1, 4) as usize;
+    let mut peak = audio
+        .samples
+        .iter()
+        .fold(0.0f32, |peak, s| peak.max(s.abs()));
+
+    if factor > 1 {
+        // Windowed sinc low pass, split into one filter per phase.
+        let taps = PHASE_TAPS * factor;
+        let centre = (taps - 1) as f64 / 2.0;
+        let kernel: Vec<f32> = (0..taps)
+            .map(|n| {
+                let t = (n as f64 - centre) / factor as f64;
+                let sinc = if t == 0.0 {
+                    1.0
+                } else {
+                    (PI * t).sin() / (PI * t)
+                };
+                let window = 0.5 - 0.5 * (2.0 * PI * (n as f64 + 0.5) / taps as f64).cos();
+                (sinc * window) as f32
+            })
+            .collect();
+        let frames = audio.frames();
+        for channel in 0..channels {
+            let input: Vec<f32> = audio.samples[channel..]
+                .iter()
+                .step_by(channels)
+                .copied()
+                .collect();
+            for i in 0..frames {
+                for phase in 0..factor {
+                    let mut sum = 0.0;
+                    for k in 0..PHASE_TAPS.min(i + 1) {
+                        sum += input[i - k] * kernel[k * factor + phase];
+                    }
+                    peak = peak.max(sum.abs());
+                }
+            }
+        }
+    }
+    20.0 * (peak as f64).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use symphonia::core::audio::SignalSpec;
+
+    use super::*;
+
+    /// A 1 kHz sine at `dbfs` in both channels of a 48 kHz track.
+    fn sine(dbfs: f64, secs: usize) -> DecodedAudio {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+        let samples = (0..48000 * secs)
+            .flat_map(|i| {
+                let s = (amplitude * (2.0 * PI * 1000.0 * i as f64 / 48000.0).sin()) as f32;
+                [s, s]
+            })
+            .collect();
+        DecodedAudio {
+            spec: SignalSpec::new(48000, Channels::FRONT_LEFT | Channels::FRONT_RIGHT),
+            samples,
+        }
+    }
+
+    #[test]
+    fn stereo_sine_reads_its_level() {
+        // The known answer of EBU Tech 3341: -23 dBFS in both channels
+        // reads -23 LUFS.
+        let loudness = Loudness::measure(&sine(-23.0, 5)).unwrap();
+        assert!((loudness.integrated_lufs + 23.0).abs() < 0.1);
+        assert!((loudness.true_peak_dbtp + 23.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn gain_stops_at_the_peak_ceiling() {
+        let quiet = Loudness {
+            integrated_lufs: -30.0,
+            true_peak_dbtp: -20.0,
+        };
+        assert!((quiet.gain(TARGET_LUFS) - 10f32.powf(14.0 / 20.0)).abs() < 1e-4);
+
+        // Bringing this one up by 14 dB would peak at +11 dBTP.
+        let peaky = Loudness {
+            integrated_lufs: -30.0,
+            true_peak_dbtp: -3.0,
+        };
+        assert!((peaky.gain(TARGET_LUFS) - 10f32.powf(2.0 / 20.0)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn silence_has_no_loudness() {
+        let mut audio = sine(-23.0, 1);
+        audio.samples.fill(0.0);
+        assert_eq!(Loudness::measure(&audio), None);
+    }
+}
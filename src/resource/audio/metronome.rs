@@ -0,0 +1,47 @@
+use std::{f64::consts::PI, path::Path};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+const SAMPLE_RATE: u32 = 44100;
+const CLICK_SECS: f64 = 0.03;
+/// Pitch of the first beat of each bar and of the others.
+const ACCENT_HZ: f64 = 1500.0;
+const BEAT_HZ: f64 = 1000.0;
+const BEATS_PER_BAR: usize = 4;
+
+/// Writes a click track with `beats` clicks at `bpm`, the first one beat in.
+///
+/// Playing clicks as a track rather than as sound effects puts each one
+/// exactly on its beat in track time, which calibration depends on.
+pub fn write_click_track(out: &Path, bpm: f64, beats: usize) -> Result<(), hound::Error> {
+    let beat_frames = 60.0 / bpm * SAMPLE_RATE as f64;
+    let click_frames = (CLICK_SECS * SAMPLE_RATE as f64) as usize;
+    let total = ((beats + 1) as f64 * beat_frames) as usize;
+    let mut samples = vec![0.0f32; total];
+
+    for beat in 1..=beats {
+        let start = (beat as f64 * beat_frames).round() as usize;
+        let hz = if (beat - 1) % BEATS_PER_BAR == 0 {
+            ACCENT_HZ
+        } else {
+            BEAT_HZ
+        };
+        for (i, sample) in samples[start..].iter_mut().take(click_frames).enumerate() {
+            let t = i as f64 / SAMPLE_RATE as f64;
+            let envelope = 1.0 - i as f64 / click_frames as f64;
+            *sample = (0.8 * envelope * (2.0 * PI * hz * t).sin()) as f32;
+        }
+    }
+
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut writer = WavWriter::create(out, spec)?;
+    for sample in samples {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()
+}
@@ -0,0 +1,258 @@
+use std::{
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use super::{
+    AudioError, AudioPlayer, PlayerState, RateMode,
+    decode::DecodeError,
+    hitsound::{HitsoundLibrary, SampleBank},
+    output::NullBackend,
+};
+use crate::resource::osufile::OsuFile;
+
+const CHANNELS: usize = 2;
+const SAMPLE_RATE: u32 = 44100;
+/// Frames rendered at a time. Hitsounds start on a block boundary, so this
+/// bounds how far they can be off.
+const BLOCK_FRAMES: usize = 64;
+/// Rendered after the music ends, so the last hitsounds can ring out.
+const TAIL_SECS: f64 = 1.0;
+const DECODE_WAIT: Duration = Duration::from_millis(1);
+
+#[derive(Debug)]
+pub enum MixdownError {
+    Audio(AudioError),
+    Decode(DecodeError),
+    Wav(hound::Error),
+}
+
+impl fmt::Display for MixdownError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MixdownError::Audio(e) => write!(f, "{e}"),
+            MixdownError::Decode(e) => write!(f, "{e}"),
+            MixdownError::Wav(e) => write!(f, "Couldn't write the WAV file: {e}"),
+        }
+    }
+}
+
+impl Error for MixdownError {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MixdownStats {
+    /// Length of the rendered file.
+    pub length_ms: f64,
+    /// Hit objects whose hitsounds were mixed in.
+    pub hitsounds: usize,
+}
+
+/// Renders a beatmap's music with every hitsound at its object time to a
+/// WAV file, faster than real time. It goes through the same player and
+/// mixer as gameplay, on a [`NullBackend`], so it sounds the same.
+pub struct Mixdown<'a> {
+    beatmap: &'a OsuFile,
+    beatmap_dir: PathBuf,
+    samples_dir: PathBuf,
+    rate: f64,
+    mode: RateMode,
+}
+
+impl<'a> Mixdown<'a> {
+    pub fn new(beatmap: &'a OsuFile, beatmap_dir: &Path, samples_dir: &Path) -> Self {
+        Self {
+            beatmap,
+            beatmap_dir: beatmap_dir.to_path_buf(),
+            samples_dir: samples_dir.to_path_buf(),
+            rate: 1.0,
+            mode: RateMode::Varispeed,
+        }
+    }
+
+    /// Plays the music at `rate`, as with Double Time or Nightcore.
+    pub fn with_rate(mut self, rate: f64, mode: RateMode) -> Self {
+        self.rate = rate;
+        self.mode = mode;
+        self
+    }
+
+    pub fn render(&self, out: &Path) -> Result<MixdownStats, MixdownError> {
+        let audio = self.beatmap_dir.join(&self.beatmap.general.audio_filename);
+        let output = NullBackend::new(CHANNELS, SAMPLE_RATE);
+        let (mut player, _decoder) = AudioPlayer::new_async(&audio);
+        player
+            .start_with(output.clone())
+            .map_err(MixdownError::Audio)?;
+        player
+            .wait_loaded(Duration::MAX)
+            .map_err(MixdownError::Decode)?;
+        player.set_rate(self.rate, self.mode);
+        player.play();
+
+        let objects = &self.beatmap.hit_objects;
+        let timing_points = &self.beatmap.timing_points;
+        let default_bank =
+            SampleBank::from_name(&self.beatmap.general.sample_set).unwrap_or(SampleBank::Normal);
+        let mut hitsounds = HitsoundLibrary::new(&self.beatmap_dir, &self.samples_dir, SAMPLE_RATE);
+        hitsounds.preload(objects, timing_points, default_bank);
+
+        let spec = WavSpec {
+            channels: CHANNELS as u16,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut writer = WavWriter::create(out, spec).map_err(MixdownError::Wav)?;
+        let mut buffer = vec![0.0; BLOCK_FRAMES * CHANNELS];
+        // Track time one block covers.
+        let block_ms = BLOCK_FRAMES as f64 / SAMPLE_RATE as f64 * 1000.0 * player.rate();
+        let mut next = 0;
+        let mut time = 0.0;
+        let mut tail = (TAIL_SECS * SAMPLE_RATE as f64) as usize;
+        let mut frames = 0;
+
+        while tail > 0 {
+            let playing = match player.state() {
+                PlayerState::Error(e) => return Err(MixdownError::Decode(e)),
+                PlayerState::Playing => true,
+                _ => false,
+            };
+            if playing {
+                time = player.get_time_ms();
+                wait_for_decoder(&player, time)?;
+            } else if next == objects.len() {
+                tail -= BLOCK_FRAMES.min(tail);
+            }
+            // Start each hitsound in the block nearest its time.
+            while next < objects.len() && (objects[next].time as f64) < time + block_ms / 2.0 {
+                hitsounds.play(&mut player, &objects[next], timing_points, default_bank);
+                next += 1;
+            }
+
+            output.pull(&mut buffer);
+            for sample in &buffer {
+                writer.write_sample(*sample).map_err(MixdownError::Wav)?;
+            }
+            frames += BLOCK_FRAMES;
+            if !playing {
+                // The music has ended; hitsounds after it still keep time.
+                time += block_ms;
+            }
+        }
+        writer.finalize().map_err(MixdownError::Wav)?;
+
+        Ok(MixdownStats {
+            length_ms: frames as f64 / SAMPLE_RATE as f64 * 1000.0,
+            hitsounds: next,
+        })
+    }
+}
+
+/// Waits until the decoder is far enough past `time_ms` that the next
+/// block won't run out of audio, which would leave a gap in the file.
+fn wait_for_decoder(player: &AudioPlayer, time_ms: f64) -> Result<(), MixdownError> {
+    loop {
+        if let PlayerState::Error(e) = player.state() {
+            return Err(MixdownError::Decode(e));
+        }
+        let progress = player.load_progress();
+        let needed = match player.spec() {
+            Some(spec) => (time_ms / 1000.0 * spec.rate as f64) as u64 + progress.ready_frames,
+            None => progress.ready_frames,
+        };
+        if player.buffer_status().finished || progress.decoded_frames >= needed {
+            return Ok(());
+        }
+        thread::sleep(DECODE_WAIT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hound::WavReader;
+
+    use super::*;
+    use crate::{
+        resource::osufile::parse_osu_reader,
+        test_util::{TempDir, write_wav},
+    };
+
+    const MAP: &str = "osu file format v14
+
+[General]
+AudioFilename: audio.wav
+SampleSet: Normal
+
+[TimingPoints]
+0,500,4,1,0,60,1,0
+
+[HitObjects]
+256,192,200,1,0,0:0:0:0:
+256,192,500,1,8,0:0:0:30:
+";
+
+    /// The block a hitsound at `ms` starts in.
+    fn onset(ms: f64) -> usize {
+        let frame = ms / 1000.0 * SAMPLE_RATE as f64;
+        (frame / BLOCK_FRAMES as f64).round() as usize * BLOCK_FRAMES
+    }
+
+    #[test]
+    fn hitsounds_land_at_their_object_times() {
+        let dir = TempDir::new("mixdown");
+        let wav = |name: &str, channels, samples: &[f32]| {
+            write_wav(&dir.join(name), SAMPLE_RATE, channels, samples)
+        };
+        wav("audio.wav", 2, &vec![0.0; SAMPLE_RATE as usize * CHANNELS]);
+        wav("normal-hitnormal.wav", 1, &[0.5; 100]);
+        wav("normal-hitclap.wav", 1, &[0.25; 100]);
+
+        let beatmap = parse_osu_reader(MAP.as_bytes());
+        let out = dir.join("mixdown.wav");
+        let stats = Mixdown::new(&beatmap, dir.path(), dir.path())
+            .render(&out)
+            .unwrap();
+        let samples: Vec<f32> = WavReader::open(&out)
+            .unwrap()
+            .into_samples()
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(stats.hitsounds, 2);
+        let frames = samples.len() / CHANNELS;
+        assert_eq!(stats.length_ms, frames as f64 / SAMPLE_RATE as f64 * 1000.0);
+        // The music, then the tail, each rounded up to whole blocks.
+        let blocks = |frames: usize| frames.div_ceil(BLOCK_FRAMES) * BLOCK_FRAMES;
+        assert_eq!(
+            frames,
+            blocks(SAMPLE_RATE as usize) + blocks((TAIL_SECS * SAMPLE_RATE as f64) as usize)
+        );
+
+        // Hit normal at the timing point's 60%, then normal and clap at the
+        // object's own 30%.
+        let expected = [(onset(200.0), 0.5 * 0.6), (onset(500.0), 0.75 * 0.3)];
+        let mut heard = Vec::new();
+        let mut frame = 0;
+        while frame < frames {
+            let level = samples[frame * CHANNELS];
+            if level == 0.0 {
+                frame += 1;
+                continue;
+            }
+            let sound = &samples[frame * CHANNELS..(frame + 100) * CHANNELS];
+            assert!(sound.iter().all(|&s| (s - level).abs() < 1e-6));
+            heard.push((frame, level));
+            frame += 100;
+        }
+        assert_eq!(heard.len(), expected.len());
+        for ((frame, level), (onset, expected)) in heard.into_iter().zip(expected) {
+            assert_eq!(frame, onset);
+            assert!((level - expected).abs() < 1e-6, "{level} != {expected}");
+        }
+    }
+}
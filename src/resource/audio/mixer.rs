@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use rtrb::{Consumer, Producer, RingBuffer};
+
+use super::{
+    bus::Gain,
+    decode::DecodedAudio,
+    resample::{Resampler, remix},
+};
+
+/// Voices that can sound at once. Starting another cuts off the oldest.
+const MAX_VOICES: usize = 64;
+
+/// A short sound effect, decoded and resampled to the output rate up front
+/// so playing it is just copying frames.
+pub struct Sample {
+    channels: usize,
+    samples: Vec<f32>,
+}
+
+impl Sample {
+    pub fn new(audio: &DecodedAudio, out_rate: u32) -> Self {
+        let channels = audio.spec.channels.count();
+        let samples = if audio.spec.rate == out_rate {
+            audio.samples.clone()
+        } else {
+            Resampler::new(audio.spec.rate, out_rate, channels).process(&audio.samples)
+        };
+        Self { channels, samples }
+    }
+
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels
+    }
+}
+
+struct Voice {
+    sample: Arc<Sample>,
+    frame: usize,
+    volume: f32,
+}
+
+/// Plays overlapping samples on top of the music inside the output
+/// callback. Voices hold their sample through an `Arc`, and hand it back
+/// through a ring when they finish, so nothing is freed on the audio
+/// thread.
+pub(super) struct Mixer {
+    voices: Vec<Voice>,
+    finished: Producer<Arc<Sample>>,
+    /// One frame of a sample, remixed to the output channels.
+    frame: Vec<f32>,
+}
+
+impl Mixer {
+    /// `queued` is how many samples can be waiting to start. The consumer
+    /// gets the samples of finished voices back; as long as it's drained
+    /// before each new sample is sent, the ring never fills up.
+    pub fn new(queued: usize) -> (Self, Consumer<Arc<Sample>>) {
+        let (finished, finished_reader) = RingBuffer::new(MAX_VOICES + queued);
+        let mixer = Self {
+            voices: Vec::with_capacity(MAX_VOICES),
+            finished,
+            frame: Vec::new(),
+        };
+        (mixer, finished_reader)
+    }
+
+    /// Sizes the mix for `channels` output channels, before the callback
+    /// starts.
+    pub fn set_channels(&mut self, channels: usize) {
+        self.frame.resize(channels, 0.0);
+    }
+
+    pub fn play(&mut self, sample: Arc<Sample>, volume: f32) {
+        if self.voices.len() == MAX_VOICES {
+            let oldest = self.voices.remove(0);
+            let _ = self.finished.push(oldest.sample);
+        }
+        self.voices.push(Voice {
+            sample,
+            frame: 0,
+            volume,
+        });
+    }
+
+    /// Adds every voice into interleaved `output`, scaled by the effects
+    /// bus, and hands back the finished ones.
+    pub fn mix(&mut self, output: &mut [f32], channels: usize, gain: &mut Gain) {
+        for out in output.chunks_mut(channels) {
+            let gain = gain.next();
+            for voice in &mut self.voices {
+                let sample = &voice.sample;
+                let start = voice.frame * sample.channels;
+                let Some(src) = sample.samples.get(start..start + sample.channels) else {
+                    continue;
+                };
+                remix(src, &mut self.frame);
+                for (o, s) in out.iter_mut().zip(&self.frame) {
+                    *o += s * voice.volume * gain;
+                }
+                voice.frame += 1;
+            }
+        }
+        let finished = &mut self.finished;
+        self.voices.retain(|voice| {
+            let playing = voice.frame < voice.sample.frames();
+            if !playing {
+                let _ = finished.push(voice.sample.clone());
+            }
+            playing
+        });
+    }
+}
@@ -0,0 +1,1024 @@
+pub mod analysis;
+pub mod bus;
+mod clock;
+pub mod decode;
+pub mod hitsound;
+pub mod loudness;
+pub mod metronome;
+pub mod mixdown;
+pub mod mixer;
+pub mod output;
+pub mod preview;
+pub mod resample;
+pub mod spectrum;
+mod stream;
+mod stretch;
+pub mod waveform;
+
+use std::{
+    error::Error,
+    fmt,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use rtrb::{Consumer, Producer, PushError, RingBuffer};
+use symphonia::core::audio::SignalSpec;
+
+use self::{
+    bus::{Bus, Gain, MIN_RAMP_SECS},
+    clock::{PlaybackClock, SharedClock},
+    decode::DecodeError,
+    mixer::{Mixer, Sample},
+    output::{CpalBackend, OutputBackend, RenderInfo},
+    resample::{Resampler, remix},
+    spectrum::{SpectrumAnalyzer, SpectrumTap},
+    stream::{Seek, StreamInfo, StreamReader},
+    stretch::TimeStretch,
+};
+
+/// Room for commands the callback hasn't picked up yet.
+const COMMAND_QUEUE: usize = 64;
+/// How long a command waits for room in the queue before it's dropped,
+/// in case the callback has stopped running.
+const SEND_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub const MIN_RATE: f64 = 0.5;
+pub const MAX_RATE: f64 = 2.0;
+
+#[derive(Debug)]
+pub enum AudioError {
+    NoDevice,
+    /// The device offers no sample format the player can write.
+    NoSupportedConfig,
+    Output(String),
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioError::NoDevice => write!(f, "No audio output device"),
+            AudioError::NoSupportedConfig => write!(f, "No supported output format"),
+            AudioError::Output(e) => write!(f, "Audio output error: {e}"),
+        }
+    }
+}
+
+impl Error for AudioError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlayerState {
+    Stopped,
+    Playing,
+    Paused,
+    Loading,
+    /// The track couldn't be opened or decoding failed partway through,
+    /// which stops playback.
+    Error(DecodeError),
+}
+
+/// `PlayerState` without the error, so it fits in an atomic.
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    Stopped,
+    Playing,
+    Paused,
+    Loading,
+    Failed,
+}
+
+impl Status {
+    fn load(state: &AtomicU8) -> Self {
+        match state.load(Ordering::Acquire) {
+            0 => Status::Stopped,
+            1 => Status::Playing,
+            2 => Status::Paused,
+            3 => Status::Loading,
+            _ => Status::Failed,
+        }
+    }
+
+    fn store(self, state: &AtomicU8) {
+        state.store(self as u8, Ordering::Release);
+    }
+
+    /// Moves from `from` to `self`, unless something else changed the
+    /// state first.
+    fn replace(self, from: Status, state: &AtomicU8) {
+        let _ = state.compare_exchange(from as u8, self as u8, Ordering::AcqRel, Ordering::Relaxed);
+    }
+}
+
+/// State of the decoded audio queued ahead of the playhead.
+#[derive(Debug, Clone, Copy)]
+pub struct BufferStatus {
+    pub buffered_ms: f64,
+    /// Playback is waiting for the decoder to catch up.
+    pub starved: bool,
+    /// How many times playback has run out of decoded audio.
+    pub underruns: u32,
+    /// Corrupt packets in the track so far, which play as silence.
+    pub skipped_packets: u32,
+    /// The decoder has reached the end of the track.
+    pub finished: bool,
+}
+
+/// How far the decoder has got, for a loading screen.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadProgress {
+    /// Frame the decoder has got to in the file.
+    pub decoded_frames: u64,
+    /// Length of the track, if the container says or the decoder has
+    /// reached the end.
+    pub total_frames: Option<u64>,
+    /// Frames decoded before the track counts as loaded, or zero until the
+    /// format is known.
+    pub ready_frames: u64,
+}
+
+impl LoadProgress {
+    /// Progress towards being loaded, from 0 to 1.
+    pub fn fraction(&self) -> f64 {
+        if self.ready_frames == 0 {
+            return 0.0;
+        }
+        (self.decoded_frames as f64 / self.ready_frames as f64).min(1.0)
+    }
+}
+
+/// Sent to [`AudioPlayer::load_events`] receivers when loading ends.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadEvent {
+    /// Enough audio is buffered to start playing.
+    Loaded,
+    Failed(DecodeError),
+}
+
+/// Receivers waiting for the track to load.
+type Listeners = Arc<Mutex<Vec<Sender<LoadEvent>>>>;
+
+fn notify(listeners: &Listeners, event: LoadEvent) {
+    for listener in listeners.lock().unwrap().drain(..) {
+        let _ = listener.send(event.clone());
+    }
+}
+
+/// How the music is sped up or slowed down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateMode {
+    /// Plays the track faster or slower, so the pitch follows the rate, as
+    /// with Nightcore.
+    Varispeed,
+    /// Keeps the pitch, as with Double Time and Half Time.
+    TimeStretch,
+}
+
+/// Requests from the player to its output callback.
+enum Command {
+    Seek(Seek),
+    PlaySample {
+        sample: Arc<Sample>,
+        volume: f32,
+    },
+    SetRate {
+        rate: f64,
+        mode: RateMode,
+    },
+    /// Ramps a bus to `level` over `secs`, starting from `from` if given.
+    Fade {
+        bus: Bus,
+        from: Option<f32>,
+        level: f32,
+        secs: f64,
+    },
+    Mute {
+        bus: Bus,
+        muted: bool,
+    },
+    Normalize {
+        gain: f32,
+    },
+}
+
+/// What the callback needs to play the track in its own format. It's made
+/// on the decoder thread once the format is known and handed over through
+/// a ring, so the callback doesn't allocate.
+struct TrackRender {
+    spec: SignalSpec,
+    resampler: Resampler,
+    stretch: TimeStretch,
+    /// One frame of the track, before remixing to the output channels.
+    frame: Vec<f32>,
+}
+
+impl TrackRender {
+    fn new(spec: SignalSpec) -> Self {
+        let channels = spec.channels.count();
+        Self {
+            spec,
+            // The step is set for the output rate on every buffer.
+            resampler: Resampler::new(spec.rate, spec.rate, channels),
+            stretch: TimeStretch::new(channels),
+            frame: vec![0.0; channels],
+        }
+    }
+}
+
+/// Everything the output callback owns. It lives in the player until the
+/// stream starts, and commands are applied to it directly until then.
+struct Playhead {
+    reader: StreamReader,
+    commands: Consumer<Command>,
+    /// Position in seconds into the track.
+    position: f64,
+    clock: PlaybackClock,
+    mixer: Mixer,
+    rate: f64,
+    mode: RateMode,
+    /// From the decoder thread, once the track's format is known.
+    tracks: Consumer<TrackRender>,
+    track: Option<TrackRender>,
+    buses: [Gain; 3],
+    /// Loudness normalization of the track, ahead of the music bus.
+    normalization: Gain,
+    /// Output sample rate, for turning ramp times into frames.
+    out_rate: u32,
+}
+
+impl Playhead {
+    fn apply(&mut self, command: Command) {
+        match command {
+            Command::Seek(seek) => {
+                self.reader.seek(seek.epoch);
+                self.position = seek.secs;
+                self.clock.reset(seek.secs);
+                self.clock.epoch = seek.epoch;
+                self.reset_stretch();
+            }
+            Command::PlaySample { sample, volume } => self.mixer.play(sample, volume),
+            Command::SetRate { rate, mode } => {
+                if mode != self.mode {
+                    self.reset_stretch();
+                }
+                self.rate = rate;
+                self.mode = mode;
+            }
+            Command::Fade {
+                bus,
+                from,
+                level,
+                secs,
+            } => {
+                let frames = self.ramp_frames(secs);
+                let gain = &mut self.buses[bus.index()];
+                if let Some(from) = from {
+                    gain.jump(from);
+                }
+                gain.set(level, frames);
+            }
+            Command::Mute { bus, muted } => {
+                let frames = self.ramp_frames(MIN_RAMP_SECS);
+                self.buses[bus.index()].set_muted(muted, frames);
+            }
+            Command::Normalize { gain } => {
+                let frames = self.ramp_frames(MIN_RAMP_SECS);
+                self.normalization.set(gain, frames);
+            }
+        }
+    }
+
+    fn ramp_frames(&self, secs: f64) -> usize {
+        (secs.max(MIN_RAMP_SECS) * self.out_rate as f64) as usize
+    }
+
+    /// Applies the music gains to the track in `output`, or just moves
+    /// their ramps on when nothing is playing.
+    fn music(&mut self, output: &mut [f32], channels: usize, playing: bool) {
+        let music = &mut self.buses[Bus::Music.index()];
+        if playing {
+            self.normalization.apply(output, channels);
+            music.apply(output, channels);
+        } else {
+            self.normalization.skip(output.len() / channels);
+            music.skip(output.len() / channels);
+        }
+    }
+
+    /// Mixes the sound effects over the music and applies the master bus.
+    fn finish(&mut self, output: &mut [f32], channels: usize) {
+        let effects = &mut self.buses[Bus::Effects.index()];
+        self.mixer.mix(output, channels, effects);
+        self.buses[Bus::Master.index()].apply(output, channels);
+    }
+
+    fn reset_stretch(&mut self) {
+        if let Some(track) = &mut self.track {
+            track.stretch.reset();
+        }
+    }
+
+    /// Whether frames come from the time stretcher rather than straight
+    /// from the resampler.
+    fn stretching(&self) -> bool {
+        self.mode == RateMode::TimeStretch && self.rate != 1.0
+    }
+}
+
+/// Plays a track through an [`OutputBackend`], the default sound device
+/// unless another is given.
+///
+/// The output callback never blocks: decoded audio reaches it through a
+/// lock-free ring, state and clock are shared through atomics, and seeks
+/// are sent to it through a wait-free queue.
+pub struct AudioPlayer {
+    state: Arc<AtomicU8>,
+    info: Arc<StreamInfo>,
+    listeners: Listeners,
+    /// `play` was called while loading, so start as soon as it's loaded.
+    autoplay: Arc<AtomicBool>,
+    clock: Arc<SharedClock>,
+    tap: Arc<SpectrumTap>,
+    commands: Producer<Command>,
+    /// A command was dropped because the queue stayed full, so later ones
+    /// don't wait until there's room again.
+    stalled: bool,
+    /// Sound effects the mixer is done with, dropped here rather than on
+    /// the audio thread.
+    finished: Consumer<Arc<Sample>>,
+    /// Seeks for the decoder thread.
+    seeks: Sender<Seek>,
+    /// The latest seek, which the callback may not have seen yet.
+    seek: Seek,
+    playhead: Option<Playhead>,
+    output: Option<Box<dyn OutputBackend>>,
+    out_rate: Option<u32>,
+    rate: f64,
+    volumes: [f32; 3],
+    muted: [bool; 3],
+    normalization: f32,
+}
+
+impl AudioPlayer {
+    /// Opens a track and starts a thread that decodes it a little ahead of
+    /// the playhead, so memory use doesn't grow with the track length.
+    ///
+    /// The player stays [`PlayerState::Loading`] until the first half
+    /// second or so is decoded; see [`wait_loaded`](Self::wait_loaded).
+    pub fn new_async(path: &Path) -> (Self, thread::JoinHandle<()>) {
+        let state = Arc::new(AtomicU8::new(Status::Loading as u8));
+        let info = Arc::new(StreamInfo::default());
+        let listeners = Listeners::default();
+        let autoplay = Arc::new(AtomicBool::new(false));
+        let (writer, reader) = stream::channel();
+        let (commands, command_reader) = RingBuffer::new(COMMAND_QUEUE);
+        let (seeks, seek_reader) = mpsc::channel();
+        let (mut tracks, track_reader) = RingBuffer::new(1);
+        let (mixer, finished) = Mixer::new(COMMAND_QUEUE);
+
+        let state_ptr = state.clone();
+        let info_ptr = info.clone();
+        let listeners_ptr = listeners.clone();
+        let autoplay_ptr = autoplay.clone();
+        let path = path.to_path_buf();
+
+        let handle = thread::spawn(move || {
+            let on_loaded = || {
+                Status::Stopped.replace(Status::Loading, &state_ptr);
+                // Checked after leaving Loading, so a concurrent `play`
+                // either sees Stopped or has already set the flag.
+                if autoplay_ptr.load(Ordering::Acquire) {
+                    Status::Playing.replace(Status::Stopped, &state_ptr);
+                }
+                notify(&listeners_ptr, LoadEvent::Loaded);
+            };
+            // The spec is only ever set once, so this always fits.
+            let on_spec = |spec| {
+                let _ = tracks.push(TrackRender::new(spec));
+            };
+            if let Err(e) =
+                stream::run_decoder(path, writer, &info_ptr, seek_reader, on_spec, on_loaded)
+            {
+                let _ = info_ptr.error.set(e.clone());
+                Status::Failed.store(&state_ptr);
+                notify(&listeners_ptr, LoadEvent::Failed(e));
+            }
+        });
+
+        let playhead = Playhead {
+            reader,
+            commands: command_reader,
+            position: 0.0,
+            clock: PlaybackClock::default(),
+            mixer,
+            rate: 1.0,
+            mode: RateMode::Varispeed,
+            tracks: track_reader,
+            track: None,
+            buses: [Gain::new(), Gain::new(), Gain::new()],
+            normalization: Gain::new(),
+            out_rate: 48000,
+        };
+        (
+            Self {
+                state,
+                info,
+                listeners,
+                autoplay,
+                clock: Arc::new(SharedClock::new()),
+                tap: Arc::new(SpectrumTap::new()),
+                commands,
+                stalled: false,
+                finished,
+                seeks,
+                seek: Seek::default(),
+                playhead: Some(playhead),
+                output: None,
+                out_rate: None,
+                rate: 1.0,
+                volumes: [1.0; 3],
+                muted: [false; 3],
+                normalization: 1.0,
+            },
+            handle,
+        )
+    }
+
+    /// Starts playing through the default sound device.
+    pub fn start(&mut self) -> Result<(), AudioError> {
+        if self.output.is_some() {
+            return Ok(());
+        }
+        self.start_with(CpalBackend::new()?)
+    }
+
+    /// Starts rendering into `backend`, such as a
+    /// [`NullBackend`](output::NullBackend) for running without a device.
+    pub fn start_with(
+        &mut self,
+        mut backend: impl OutputBackend + 'static,
+    ) -> Result<(), AudioError> {
+        if self.output.is_some() {
+            return Ok(());
+        }
+
+        let state = self.state.clone();
+        let info = self.info.clone();
+        let shared_clock = self.clock.clone();
+        let tap = self.tap.clone();
+
+        let format = backend.format();
+        let out_channels = format.channels;
+        let out_rate = format.rate;
+
+        let Some(mut playhead) = self.playhead.take() else {
+            return Ok(());
+        };
+        playhead.out_rate = out_rate;
+        playhead.mixer.set_channels(out_channels);
+        tap.set_rate(out_rate);
+
+        let render = move |output: &mut [f32], render_info: &RenderInfo| {
+            output.fill(0.0);
+            while let Ok(command) = playhead.commands.pop() {
+                playhead.apply(command);
+            }
+            // Keep draining while paused, so seeks don't wait on stale audio.
+            playhead.reader.fill();
+            if let Ok(track) = playhead.tracks.pop() {
+                playhead.track = Some(track);
+            }
+
+            let playing = Status::load(&state) == Status::Playing;
+            let stretching = playhead.stretching();
+            let track = match &mut playhead.track {
+                Some(track) if playing => track,
+                _ => {
+                    playhead.clock.callback_at = None;
+                    shared_clock.store(&playhead.clock);
+                    playhead.music(output, out_channels, false);
+                    tap.write(output, out_channels, render_info.latency);
+                    playhead.finish(output, out_channels);
+                    return;
+                }
+            };
+
+            let TrackRender {
+                spec,
+                resampler,
+                stretch,
+                frame,
+            } = track;
+            let channels = spec.channels.count();
+            let track_rate = spec.rate as f64;
+
+            // Track frames the playhead moves per output frame.
+            let base_step = track_rate / out_rate as f64;
+            let advance = base_step * playhead.rate;
+            resampler.set_step(if stretching { base_step } else { advance });
+            let radius = resampler.radius() as f64;
+            let (behind, ahead) = if stretching {
+                let (behind, ahead) = TimeStretch::reach(base_step, playhead.rate);
+                (behind + radius, ahead + radius)
+            } else {
+                (radius, radius)
+            };
+
+            let reader = &mut playhead.reader;
+            let window_start = reader.start_frame as f64;
+            let end = reader.end_frame(channels) as f64;
+            // Keep enough decoded audio past the playhead to render from.
+            let lookahead = if reader.finished { 0.0 } else { ahead };
+            let start = playhead.position;
+            let mut pos = start * track_rate;
+            let mut starved = false;
+            for out in output.chunks_mut(out_channels) {
+                if pos + lookahead >= end {
+                    if reader.finished {
+                        Status::Stopped.replace(Status::Playing, &state);
+                    } else {
+                        starved = true;
+                    }
+                    break;
+                }
+                if stretching {
+                    let src = &reader.window;
+                    stretch.next_frame(resampler, src, window_start, pos, playhead.rate, frame);
+                } else {
+                    resampler.frame_at(&reader.window, pos - window_start, frame);
+                }
+                remix(frame, out);
+                pos += advance;
+            }
+            if info.starved.swap(starved, Ordering::Relaxed) != starved && starved {
+                info.underruns.fetch_add(1, Ordering::Relaxed);
+            }
+            playhead.position = pos / track_rate;
+            reader.release_before((pos - behind).max(0.0) as u64, channels);
+            info.buffered_frames
+                .store(reader.frames_ahead(pos as u64, channels), Ordering::Relaxed);
+
+            let clock = &mut playhead.clock;
+            clock.position_before = start;
+            clock.callback_span = playhead.position - start;
+            clock.callback_length = (output.len() / out_channels) as f64 / out_rate as f64;
+            clock.callback_at = render_info.live.then(Instant::now);
+            clock.rate = playhead.rate;
+            clock.latency = render_info.latency;
+            shared_clock.store(clock);
+
+            playhead.music(output, out_channels, true);
+            tap.write(output, out_channels, render_info.latency);
+            playhead.finish(output, out_channels);
+        };
+
+        backend.start(Box::new(render))?;
+        self.output = Some(Box::new(backend));
+        self.out_rate = Some(out_rate);
+        Ok(())
+    }
+
+    /// Starts playback, or resumes from where it was paused. While the
+    /// track is loading, playback starts once it's loaded.
+    pub fn play(&mut self) {
+        self.autoplay.store(true, Ordering::Release);
+        Status::Playing.replace(Status::Stopped, &self.state);
+        Status::Playing.replace(Status::Paused, &self.state);
+    }
+
+    pub fn pause(&mut self) {
+        self.autoplay.store(false, Ordering::Release);
+        Status::Paused.replace(Status::Playing, &self.state);
+    }
+
+    pub fn stop(&mut self) {
+        self.autoplay.store(false, Ordering::Release);
+        Status::Stopped.replace(Status::Playing, &self.state);
+        Status::Stopped.replace(Status::Paused, &self.state);
+        self.send_seek(0.0);
+    }
+
+    /// Moves playback to `ms` into the track without changing whether it is
+    /// playing or paused. The decoder thread seeks the file and refills the
+    /// buffer from there, so this doesn't depend on how much was decoded.
+    ///
+    /// Negative times play silence until the track starts, as for a
+    /// beatmap's lead-in, with the clock running through them.
+    pub fn seek(&mut self, ms: f64) {
+        let mut secs = ms / 1000.0;
+        if let (Some(total), Some(spec)) = (self.info.total_frames(), self.info.spec()) {
+            secs = secs.min(total as f64 / spec.rate as f64);
+        }
+        self.send_seek(secs);
+    }
+
+    fn send_seek(&mut self, secs: f64) {
+        self.seek = Seek {
+            epoch: self.seek.epoch + 1,
+            secs,
+        };
+        // The decoder thread only stops once the player is gone.
+        let _ = self.seeks.send(self.seek);
+        self.send(Command::Seek(self.seek));
+    }
+
+    fn send(&mut self, mut command: Command) {
+        while self.finished.pop().is_ok() {}
+        if let Some(playhead) = &mut self.playhead {
+            playhead.apply(command);
+            return;
+        }
+        // The callback drains the queue every buffer, so a full queue
+        // usually means waiting for the next one. If the callback has
+        // stopped running, the command is dropped rather than waiting
+        // forever.
+        let deadline = Instant::now() + SEND_TIMEOUT;
+        loop {
+            match self.commands.push(command) {
+                Ok(()) => {
+                    self.stalled = false;
+                    return;
+                }
+                Err(PushError::Full(c)) => command = c,
+            }
+            if self.stalled || Instant::now() >= deadline {
+                if !self.stalled {
+                    eprintln!("Audio output stopped taking commands; dropping them");
+                }
+                self.stalled = true;
+                return;
+            }
+            thread::yield_now();
+        }
+    }
+
+    /// Plays the music at `rate` times normal speed, clamped to
+    /// [`MIN_RATE`]..=[`MAX_RATE`]. The clock keeps reporting track time,
+    /// so beatmap timing stays in step.
+    pub fn set_rate(&mut self, rate: f64, mode: RateMode) {
+        self.rate = rate.clamp(MIN_RATE, MAX_RATE);
+        self.send(Command::SetRate {
+            rate: self.rate,
+            mode,
+        });
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Sets a bus's linear gain. The change is ramped over a few
+    /// milliseconds so it doesn't click.
+    pub fn set_volume(&mut self, bus: Bus, level: f32) {
+        self.fade(bus, level, Duration::ZERO);
+    }
+
+    pub fn volume(&self, bus: Bus) -> f32 {
+        self.volumes[bus.index()]
+    }
+
+    /// Silences a bus without forgetting its volume.
+    pub fn set_muted(&mut self, bus: Bus, muted: bool) {
+        self.muted[bus.index()] = muted;
+        self.send(Command::Mute { bus, muted });
+    }
+
+    pub fn is_muted(&self, bus: Bus) -> bool {
+        self.muted[bus.index()]
+    }
+
+    /// Ramps a bus from its current gain to `level` over `duration`.
+    pub fn fade(&mut self, bus: Bus, level: f32, duration: Duration) {
+        self.volumes[bus.index()] = level;
+        self.send(Command::Fade {
+            bus,
+            from: None,
+            level,
+            secs: duration.as_secs_f64(),
+        });
+    }
+
+    /// Starts a bus from silence and ramps it up to `level`, as when a
+    /// song preview starts.
+    pub fn fade_in(&mut self, bus: Bus, level: f32, duration: Duration) {
+        self.volumes[bus.index()] = level;
+        self.send(Command::Fade {
+            bus,
+            from: Some(0.0),
+            level,
+            secs: duration.as_secs_f64(),
+        });
+    }
+
+    /// Ramps a bus down to silence, as when the player fails.
+    pub fn fade_out(&mut self, bus: Bus, duration: Duration) {
+        self.fade(bus, 0.0, duration);
+    }
+
+    /// Sets the track's loudness normalization gain, usually from
+    /// [`Loudness::gain`](loudness::Loudness::gain). It scales the music
+    /// on top of the music bus volume, so settings don't have to allow
+    /// for it.
+    pub fn set_normalization(&mut self, gain: f32) {
+        self.normalization = gain;
+        self.send(Command::Normalize { gain });
+    }
+
+    pub fn normalization(&self) -> f32 {
+        self.normalization
+    }
+
+    /// An analyser of the music as it's heard, after the music bus, for
+    /// audio-reactive visuals. Any number can be made, and reading them
+    /// never holds up the output.
+    pub fn spectrum_analyzer(&self) -> SpectrumAnalyzer {
+        SpectrumAnalyzer::new(self.tap.clone())
+    }
+
+    /// Plays a sound effect over the music, starting with the next buffer
+    /// the device asks for. `volume` scales it linearly.
+    pub fn play_sample(&mut self, sample: Arc<Sample>, volume: f32) {
+        self.send(Command::PlaySample { sample, volume });
+    }
+
+    /// Sample rate of the output stream, once started. Sound effects
+    /// should be prepared at this rate.
+    pub fn output_rate(&self) -> Option<u32> {
+        self.out_rate
+    }
+
+    /// Playback position in milliseconds, derived from the frames the
+    /// device has consumed and corrected for output latency. It holds still
+    /// while paused and picks up from the same point on resume.
+    pub fn get_time_ms(&self) -> f64 {
+        let clock = self.clock.load();
+        if clock.epoch != self.seek.epoch {
+            // The callback hasn't picked up the latest seek yet.
+            return self.seek.secs * 1000.0;
+        }
+        clock.time_ms()
+    }
+
+    /// Length of the track in milliseconds, or zero if the container
+    /// doesn't say and the decoder hasn't reached the end yet.
+    pub fn duration_ms(&self) -> f64 {
+        match (self.info.total_frames(), self.info.spec()) {
+            (Some(total), Some(spec)) => total as f64 / spec.rate as f64 * 1000.0,
+            _ => 0.0,
+        }
+    }
+
+    /// Sample rate and channel layout of the decoded track, once known.
+    pub fn spec(&self) -> Option<SignalSpec> {
+        self.info.spec()
+    }
+
+    pub fn buffer_status(&self) -> BufferStatus {
+        let buffered_ms = match self.info.spec() {
+            Some(spec) => {
+                let frames = self.info.buffered_frames.load(Ordering::Relaxed);
+                frames as f64 / spec.rate as f64 * 1000.0
+            }
+            None => 0.0,
+        };
+        BufferStatus {
+            buffered_ms,
+            starved: self.info.starved.load(Ordering::Relaxed),
+            underruns: self.info.underruns.load(Ordering::Relaxed),
+            skipped_packets: self.info.skipped_packets.load(Ordering::Relaxed),
+            finished: self.info.finished.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn state(&self) -> PlayerState {
+        match Status::load(&self.state) {
+            Status::Stopped => PlayerState::Stopped,
+            Status::Playing => PlayerState::Playing,
+            Status::Paused => PlayerState::Paused,
+            Status::Loading => PlayerState::Loading,
+            Status::Failed => PlayerState::Error(
+                self.info
+                    .error
+                    .get()
+                    .cloned()
+                    .unwrap_or(DecodeError::Stream(String::new())),
+            ),
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        Status::load(&self.state) == Status::Playing
+    }
+
+    pub fn is_paused(&self) -> bool {
+        Status::load(&self.state) == Status::Paused
+    }
+
+    /// Whether enough of the track is decoded to start playing. Also true
+    /// once loading has failed.
+    pub fn is_loaded(&self) -> bool {
+        Status::load(&self.state) != Status::Loading
+    }
+
+    pub fn load_progress(&self) -> LoadProgress {
+        LoadProgress {
+            decoded_frames: self.info.decoded_frames.load(Ordering::Relaxed),
+            total_frames: self.info.total_frames(),
+            ready_frames: self.info.ready_frames.load(Ordering::Relaxed),
+        }
+    }
+
+    /// A receiver that gets one [`LoadEvent`] when loading ends, or right
+    /// away if it already has.
+    pub fn load_events(&self) -> Receiver<LoadEvent> {
+        let (sender, receiver) = mpsc::channel();
+        // Checking the state under the lock means the decoder thread either
+        // sees this listener or has already left Loading.
+        let mut listeners = self.listeners.lock().unwrap();
+        match self.state() {
+            PlayerState::Loading => listeners.push(sender),
+            PlayerState::Error(e) => {
+                let _ = sender.send(LoadEvent::Failed(e));
+            }
+            _ => {
+                let _ = sender.send(LoadEvent::Loaded);
+            }
+        }
+        receiver
+    }
+
+    /// Blocks until the track is loaded or `timeout` passes. Returns
+    /// whether it loaded in time, or why it couldn't be loaded.
+    pub fn wait_loaded(&self, timeout: Duration) -> Result<bool, DecodeError> {
+        match self.load_events().recv_timeout(timeout) {
+            Ok(LoadEvent::Loaded) => Ok(true),
+            Ok(LoadEvent::Failed(e)) => Err(e),
+            Err(RecvTimeoutError::Timeout) => Ok(false),
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(DecodeError::Stream("decoder thread stopped".to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use symphonia::core::audio::Channels;
+
+    use super::{decode::DecodedAudio, output::NullBackend, *};
+    use crate::test_util::{TempDir, write_wav};
+
+    const RATE: u32 = 48000;
+    /// Level of every sample of the test track.
+    const LEVEL: f32 = 0.5;
+
+    /// A player on a 3 s stereo track holding `LEVEL` throughout, at the
+    /// output rate so frames come through unchanged. The track lives in
+    /// the returned directory.
+    fn player(name: &str) -> (AudioPlayer, NullBackend, TempDir) {
+        let dir = TempDir::new(name);
+        let path = dir.join("track.wav");
+        write_wav(&path, RATE, 2, &vec![LEVEL; RATE as usize * 3 * 2]);
+
+        let backend = NullBackend::new(2, RATE);
+        let (mut player, _decoder) = AudioPlayer::new_async(&path);
+        player.start_with(backend.clone()).unwrap();
+        assert!(player.wait_loaded(Duration::from_secs(5)).unwrap());
+        (player, backend, dir)
+    }
+
+    /// Lets the decoder catch up with the playhead, so the next pulls
+    /// don't run out of audio.
+    fn settle(player: &AudioPlayer, backend: &NullBackend) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            backend.pull(&mut []);
+            if player.buffer_status().buffered_ms >= 500.0 {
+                return;
+            }
+            assert!(Instant::now() < deadline, "decoder never caught up");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn pull(backend: &NullBackend, frames: usize) -> Vec<f32> {
+        let mut output = vec![0.0; frames * 2];
+        assert!(backend.pull(&mut output));
+        output
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn clock_follows_pulled_frames() {
+        let (mut player, backend, _dir) = player("clock");
+        player.play();
+        settle(&player, &backend);
+        assert_eq!(player.get_time_ms(), 0.0);
+
+        let output = pull(&backend, 480);
+        assert!(close(player.get_time_ms(), 10.0));
+        assert!(output.iter().all(|&s| s == LEVEL));
+        pull(&backend, 4800);
+        assert!(close(player.get_time_ms(), 110.0));
+    }
+
+    #[test]
+    fn seek_pause_and_resume() {
+        let (mut player, backend, _dir) = player("seek");
+        player.play();
+        player.seek(1000.0);
+        assert_eq!(player.get_time_ms(), 1000.0);
+        settle(&player, &backend);
+        pull(&backend, 480);
+        assert!(close(player.get_time_ms(), 1010.0));
+
+        player.pause();
+        let output = pull(&backend, 480);
+        assert!(player.is_paused());
+        assert!(close(player.get_time_ms(), 1010.0));
+        assert!(output.iter().all(|&s| s == 0.0));
+
+        player.play();
+        let output = pull(&backend, 480);
+        assert!(close(player.get_time_ms(), 1020.0));
+        assert!(output.iter().all(|&s| s == LEVEL));
+
+        // Before the start of the track it's silent, with the clock running.
+        player.seek(-20.0);
+        settle(&player, &backend);
+        let output = pull(&backend, 480);
+        assert!(close(player.get_time_ms(), -10.0));
+        assert!(output.iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn samples_mix_over_the_music() {
+        let (mut player, backend, _dir) = player("mix");
+        let spec = SignalSpec::new(RATE, Channels::FRONT_LEFT);
+        let audio = DecodedAudio {
+            spec,
+            samples: vec![0.25; 100],
+        };
+        let sample = Arc::new(Sample::new(&audio, RATE));
+        player.play();
+        settle(&player, &backend);
+
+        player.play_sample(sample.clone(), 0.5);
+        player.play_sample(sample.clone(), 1.0);
+        let output = pull(&backend, 200);
+        let (with, after) = output.split_at(100 * 2);
+        assert!(with.iter().all(|&s| s == LEVEL + 0.125 + 0.25));
+        assert!(after.iter().all(|&s| s == LEVEL));
+
+        // Finished voices hand their sample back to be dropped off the
+        // audio thread, on the next command.
+        player.set_volume(Bus::Effects, 1.0);
+        assert_eq!(Arc::strong_count(&sample), 1);
+    }
+
+    #[test]
+    fn commands_are_dropped_once_the_output_stops() {
+        let (mut player, _backend, _dir) = player("stalled");
+        // Nothing pulls, so the queue fills up and stays full.
+        let start = Instant::now();
+        for _ in 0..COMMAND_QUEUE * 2 {
+            player.set_volume(Bus::Music, 0.5);
+        }
+        assert!(start.elapsed() < SEND_TIMEOUT * 5);
+    }
+
+    #[test]
+    fn buses_ramp_to_their_levels() {
+        let (mut player, backend, _dir) = player("ramp");
+        player.play();
+        settle(&player, &backend);
+
+        // Instant changes still take the shortest ramp.
+        let ramp = (MIN_RAMP_SECS * RATE as f64) as usize;
+        player.set_volume(Bus::Music, 0.0);
+        let output = pull(&backend, ramp + 10);
+        assert!(output[0] < LEVEL && output[0] > LEVEL * 0.99);
+        assert!((output[ramp] - LEVEL / 2.0).abs() < 0.01);
+        assert!(output[ramp * 2..].iter().all(|&s| s == 0.0));
+
+        player.fade(Bus::Music, 1.0, Duration::from_millis(100));
+        let output = pull(&backend, 4800);
+        assert!((output[4800] - LEVEL / 2.0).abs() < 0.01);
+        assert!((output[4800 * 2 - 1] - LEVEL).abs() < 1e-4);
+
+        player.fade_in(Bus::Master, 0.5, Duration::from_millis(100));
+        let output = pull(&backend, 4800);
+        assert!(output[0] < 0.01);
+        assert!((output[4800] - LEVEL / 4.0).abs() < 0.01);
+        let output = pull(&backend, 10);
+        assert!(output.iter().all(|&s| s == LEVEL / 2.0));
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use cpal::{
+    FromSample, OutputCallbackInfo, SampleFormat, SizedSample, Stream, SupportedStreamConfig,
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+};
+
+use super::AudioError;
+
+/// Output formats the player can convert to, best first.
+const FORMATS: [SampleFormat; 5] = [
+    SampleFormat::F32,
+    SampleFormat::I32,
+    SampleFormat::I16,
+    SampleFormat::U16,
+    SampleFormat::F64,
+];
+
+const PREFERRED_RATES: [u32; 2] = [48000, 44100];
+
+/// Frames rendered at a time on a sound device. Bigger device buffers are
+/// rendered in several goes, so the scratch buffer is allocated up front.
+const MAX_RENDER_FRAMES: usize = 8192;
+
+/// Layout of the interleaved `f32` frames a backend asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputFormat {
+    pub channels: usize,
+    pub rate: u32,
+}
+
+/// What the render callback is told about each buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderInfo {
+    /// Delay between the callback and its first frame being heard.
+    pub latency: Duration,
+    /// The buffer is played in real time, so the clock may interpolate
+    /// between callbacks. Otherwise time only moves a buffer at a time.
+    pub live: bool,
+}
+
+/// Fills a buffer of interleaved frames in the backend's format.
+pub type RenderFn = Box<dyn FnMut(&mut [f32], &RenderInfo) + Send>;
+
+/// Where the player's output goes. The backend decides when buffers are
+/// rendered; the player only supplies the callback.
+pub trait OutputBackend {
+    fn format(&self) -> OutputFormat;
+
+    /// Starts asking `render` for audio, until the backend is dropped.
+    fn start(&mut self, render: RenderFn) -> Result<(), AudioError>;
+}
+
+/// Plays through a sound device with cpal.
+pub struct CpalBackend {
+    device: cpal::Device,
+    config: SupportedStreamConfig,
+    stream: Option<Stream>,
+}
+
+impl CpalBackend {
+    /// Uses the default output device of the default host.
+    pub fn new() -> Result<Self, AudioError> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or(AudioError::NoDevice)?;
+        let config = choose_config(&device)?;
+        Ok(Self {
+            device,
+            config,
+            stream: None,
+        })
+    }
+}
+
+impl OutputBackend for CpalBackend {
+    fn format(&self) -> OutputFormat {
+        OutputFormat {
+            channels: self.config.channels() as usize,
+            rate: self.config.sample_rate().0,
+        }
+    }
+
+    fn start(&mut self, render: RenderFn) -> Result<(), AudioError> {
+        let stream = build_stream(&self.device, &self.config, render)?;
+        stream
+            .play()
+            .map_err(|e| AudioError::Output(e.to_string()))?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+}
+
+/// An output with no device behind it. Nothing is rendered until
+/// [`pull`](Self::pull) asks for it, so the player's clock runs on the
+/// frames pulled rather than on wall time. For tests and offline rendering.
+///
+/// Clones share the same output, so one can be handed to the player and
+/// another kept to pull with.
+#[derive(Clone)]
+pub struct NullBackend {
+    format: OutputFormat,
+    render: Arc<Mutex<Option<RenderFn>>>,
+}
+
+impl NullBackend {
+    pub fn new(channels: usize, rate: u32) -> Self {
+        Self {
+            format: OutputFormat { channels, rate },
+            render: Arc::default(),
+        }
+    }
+
+    /// Renders the next buffer into `output`. Returns `false`, leaving it
+    /// silent, if the player hasn't started yet.
+    pub fn pull(&self, output: &mut [f32]) -> bool {
+        let info = RenderInfo {
+            latency: Duration::ZERO,
+            live: false,
+        };
+        match self.render.lock().unwrap().as_mut() {
+            Some(render) => {
+                render(output, &info);
+                true
+            }
+            None => {
+                output.fill(0.0);
+                false
+            }
+        }
+    }
+}
+
+impl OutputBackend for NullBackend {
+    fn format(&self) -> OutputFormat {
+        self.format
+    }
+
+    fn start(&mut self, render: RenderFn) -> Result<(), AudioError> {
+        *self.render.lock().unwrap() = Some(render);
+        Ok(())
+    }
+}
+
+fn format_rank(format: SampleFormat) -> Option<usize> {
+    FORMATS.iter().position(|f| *f == format)
+}
+
+/// Picks the stream config to open: the device default when its sample
+/// format is supported, otherwise the best supported range, preferring
+/// float formats, stereo and a common sample rate.
+fn choose_config(device: &cpal::Device) -> Result<SupportedStreamConfig, AudioError> {
+    if let Ok(config) = device.default_output_config()
+        && format_rank(config.sample_format()).is_some()
+    {
+        return Ok(config);
+    }
+
+    let ranges = device
+        .supported_output_configs()
+        .map_err(|e| AudioError::Output(e.to_string()))?;
+    let best = ranges
+        .filter_map(|range| {
+            let rank = format_rank(range.sample_format())?;
+            Some((rank, range.channels() != 2, range))
+        })
+        .min_by_key(|(rank, not_stereo, _)| (*rank, *not_stereo))
+        .map(|(_, _, range)| range)
+        .ok_or(AudioError::NoSupportedConfig)?;
+
+    let config = PREFERRED_RATES
+        .iter()
+        .find_map(|&rate| best.try_with_sample_rate(cpal::SampleRate(rate)))
+        .unwrap_or_else(|| best.with_max_sample_rate());
+    Ok(config)
+}
+
+/// Opens an output stream in the config's sample format. `render` always
+/// fills `f32` samples, which are converted to the device format.
+fn build_stream(
+    device: &cpal::Device,
+    config: &SupportedStreamConfig,
+    render: RenderFn,
+) -> Result<Stream, AudioError> {
+    match config.sample_format() {
+        SampleFormat::F32 => build_typed::<f32>(device, config, render),
+        SampleFormat::I32 => build_typed::<i32>(device, config, render),
+        SampleFormat::I16 => build_typed::<i16>(device, config, render),
+        SampleFormat::U16 => build_typed::<u16>(device, config, render),
+        SampleFormat::F64 => build_typed::<f64>(device, config, render),
+        _ => Err(AudioError::NoSupportedConfig),
+    }
+}
+
+fn build_typed<T>(
+    device: &cpal::Device,
+    config: &SupportedStreamConfig,
+    mut render: RenderFn,
+) -> Result<Stream, AudioError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels() as usize;
+    let rate = config.sample_rate().0 as f64;
+    let mut scratch = vec![0.0; MAX_RENDER_FRAMES * channels];
+    device
+        .build_output_stream(
+            &config.config(),
+            move |output: &mut [T], info: &OutputCallbackInfo| {
+                let timestamp = info.timestamp();
+                let latency = timestamp
+                    .playback
+                    .duration_since(&timestamp.callback)
+                    .unwrap_or_default();
+                for (i, output) in output.chunks_mut(scratch.len()).enumerate() {
+                    // Later pieces are heard after the ones before them.
+                    let ahead = (i * MAX_RENDER_FRAMES) as f64 / rate;
+                    let info = RenderInfo {
+                        latency: latency + Duration::from_secs_f64(ahead),
+                        live: true,
+                    };
+                    let scratch = &mut scratch[..output.len()];
+                    render(scratch, &info);
+                    for (out, sample) in output.iter_mut().zip(scratch.iter()) {
+                        *out = T::from_sample(*sample);
+                    }
+                }
+            },
+            |err| eprintln!("Stream error: {}", err),
+            None,
+        )
+        .map_err(|e| AudioError::Output(e.to_string()))
+}
@@ -0,0 +1,178 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use super::{AudioError, AudioPlayer, PlayerState, bus::Bus};
+
+/// Where the preview starts when the beatmap doesn't set `PreviewTime`.
+const DEFAULT_POSITION: f64 = 0.4;
+/// How long to wait for the track's length before starting from the
+/// beginning instead, for containers that don't say how long they are.
+const LENGTH_WAIT: Duration = Duration::from_secs(1);
+const LOOP_LENGTH: Duration = Duration::from_secs(20);
+const FADE_IN: Duration = Duration::from_millis(800);
+const CROSS_FADE: Duration = Duration::from_millis(500);
+/// Fade out before jumping back to the start of the preview.
+const LOOP_FADE: Duration = Duration::from_millis(300);
+
+struct Preview {
+    audio: PathBuf,
+    player: AudioPlayer,
+    /// Where the preview starts, once known. With no `PreviewTime` that
+    /// needs the track's length, so it waits for the first decoded audio,
+    /// or until [`LENGTH_WAIT`] after `opened` if the length isn't known.
+    start_ms: Option<f64>,
+    opened: Instant,
+    /// Fading out ahead of looping back.
+    looping: bool,
+    /// Started, but silent until the audio from the start comes through.
+    fade_pending: bool,
+}
+
+impl Preview {
+    fn begin(&mut self, start_ms: f64) {
+        self.player.set_volume(Bus::Music, 0.0);
+        self.player.seek(start_ms);
+        self.player.play();
+        self.start_ms = Some(start_ms);
+        self.looping = false;
+        self.fade_pending = true;
+    }
+}
+
+/// Plays song previews for song select: each starts at the beatmap's
+/// `PreviewTime`, fades in, loops after a while, and cross-fades into the
+/// next one. Tracks are streamed, so switching doesn't wait on decoding.
+///
+/// [`update`](Self::update) has to be called regularly, e.g. every frame.
+pub struct PreviewPlayer {
+    current: Option<Preview>,
+    /// Previews fading out, kept until they're silent.
+    outgoing: Vec<(AudioPlayer, Instant)>,
+    loop_length: Duration,
+    fade_in: Duration,
+    cross_fade: Duration,
+}
+
+impl Default for PreviewPlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PreviewPlayer {
+    pub fn new() -> Self {
+        Self {
+            current: None,
+            outgoing: Vec::new(),
+            loop_length: LOOP_LENGTH,
+            fade_in: FADE_IN,
+            cross_fade: CROSS_FADE,
+        }
+    }
+
+    /// How long a preview plays before going back to its start.
+    pub fn with_loop_length(mut self, length: Duration) -> Self {
+        self.loop_length = length;
+        self
+    }
+
+    pub fn with_fades(mut self, fade_in: Duration, cross_fade: Duration) -> Self {
+        self.fade_in = fade_in;
+        self.cross_fade = cross_fade;
+        self
+    }
+
+    /// Switches to the preview of `audio`, starting at `preview_time` ms
+    /// or 40% into the track when it's -1 (from the start, if the track's
+    /// length can't be found out). Difficulties of the same set share
+    /// their audio, so picking another one keeps playing.
+    ///
+    /// `normalization` is the track's loudness normalization gain, so
+    /// switching between loud and quiet songs keeps a steady level.
+    pub fn play(
+        &mut self,
+        audio: &Path,
+        preview_time: i32,
+        normalization: f32,
+    ) -> Result<(), AudioError> {
+        if self.current.as_ref().is_some_and(|p| p.audio == audio) {
+            return Ok(());
+        }
+        self.stop();
+
+        let (mut player, _decoder) = AudioPlayer::new_async(audio);
+        // Silent until the fade in, which starts once the preview is found.
+        player.set_volume(Bus::Music, 0.0);
+        player.set_normalization(normalization);
+        player.start()?;
+        let mut preview = Preview {
+            audio: audio.to_path_buf(),
+            player,
+            start_ms: None,
+            opened: Instant::now(),
+            looping: false,
+            fade_pending: false,
+        };
+        if preview_time >= 0 {
+            preview.begin(preview_time as f64);
+        }
+        self.current = Some(preview);
+        Ok(())
+    }
+
+    /// Fades out the current preview, as when leaving song select.
+    pub fn stop(&mut self) {
+        if let Some(mut preview) = self.current.take() {
+            preview.player.fade_out(Bus::Music, self.cross_fade);
+            let until = Instant::now() + self.cross_fade;
+            self.outgoing.push((preview.player, until));
+        }
+    }
+
+    /// Starts previews whose position waited on the track's length, fades
+    /// them in once they're heard, loops back at the end of the preview
+    /// and drops faded out players.
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        self.outgoing.retain(|(_, until)| now < *until);
+
+        let Some(preview) = &mut self.current else {
+            return;
+        };
+        let player = &mut preview.player;
+        let Some(start) = preview.start_ms else {
+            // The length may only be known some time after loading, or
+            // not until the whole track is decoded.
+            let duration = player.duration_ms();
+            if duration > 0.0 {
+                preview.begin((duration * DEFAULT_POSITION).floor());
+            } else if now >= preview.opened + LENGTH_WAIT {
+                preview.begin(0.0);
+            }
+            return;
+        };
+
+        let end = start + self.loop_length.as_secs_f64() * 1000.0;
+        let time = player.get_time_ms();
+        let ended = player.state() == PlayerState::Stopped;
+        if ended || (preview.looping && time >= end) {
+            preview.begin(start);
+        } else if preview.fade_pending {
+            // The clock only moves on from the seek once audio is playing.
+            if player.is_playing() && time > start {
+                player.fade_in(Bus::Music, 1.0, self.fade_in);
+                preview.fade_pending = false;
+            }
+        } else if !preview.looping && time >= end - LOOP_FADE.as_secs_f64() * 1000.0 {
+            player.fade_out(Bus::Music, LOOP_FADE);
+            preview.looping = true;
+        }
+    }
+
+    /// The player of the current preview, e.g. to set its volume.
+    pub fn player(&mut self) -> Option<&mut AudioPlayer> {
+        self.current.as_mut().map(|p| &mut p.player)
+    }
+}
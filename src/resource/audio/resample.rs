@@ -0,0 +1,219 @@
+use std::f64::consts::PI;
+
+/// Zero crossings of the sinc kernel on each side of the centre.
+const HALF_WIDTH: usize = 16;
+/// Table entries per zero crossing; lookups interpolate between them.
+const PHASES: usize = 256;
+
+/// Band-limited sample rate converter using a Blackman-windowed sinc.
+///
+/// When downsampling the kernel is stretched so its cutoff sits below the
+/// new Nyquist frequency, which keeps high frequencies from aliasing.
+pub struct Resampler {
+    channels: usize,
+    /// Source frames advanced per output frame.
+    step: f64,
+    /// Kernel cutoff relative to the source Nyquist frequency.
+    cutoff: f64,
+    table: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(src_rate: u32, dst_rate: u32, channels: usize) -> Self {
+        let step = src_rate as f64 / dst_rate as f64;
+        let table = (0..=HALF_WIDTH * PHASES)
+            .map(|i| {
+                let x = i as f64 / PHASES as f64;
+                let sinc = if i == 0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                let t = x / HALF_WIDTH as f64;
+                let window = 0.42 + 0.5 * (PI * t).cos() + 0.08 * (2.0 * PI * t).cos();
+                (sinc * window) as f32
+            })
+            .collect();
+        Self {
+            channels,
+            step,
+            cutoff: (1.0 / step).min(1.0),
+            table,
+        }
+    }
+
+    pub fn step(&self) -> f64 {
+        self.step
+    }
+
+    /// Changes how many source frames each output frame advances, as when
+    /// the playback rate changes.
+    pub fn set_step(&mut self, step: f64) {
+        self.step = step;
+        self.cutoff = (1.0 / step).min(1.0);
+    }
+
+    /// Source frames the kernel reaches on either side of a position.
+    pub fn radius(&self) -> usize {
+        (HALF_WIDTH as f64 / self.cutoff).ceil() as usize
+    }
+
+    fn kernel(&self, x: f64) -> f32 {
+        let pos = x.abs() * PHASES as f64;
+        let i = pos as usize;
+        if i >= HALF_WIDTH * PHASES {
+            return 0.0;
+        }
+        let frac = (pos - i as f64) as f32;
+        self.table[i] + (self.table[i + 1] - self.table[i]) * frac
+    }
+
+    /// Writes the frame at fractional source position `pos` into `out`.
+    /// `src` is interleaved; frames outside it count as silence.
+    pub fn frame_at(&self, src: &[f32], pos: f64, out: &mut [f32]) {
+        let ch = self.channels;
+        let frames = (src.len() / ch) as i64;
+        let centre = pos.floor();
+        let frac = pos - centre;
+        let centre = centre as i64;
+        out[..ch].fill(0.0);
+
+        if frac == 0.0 && self.cutoff >= 1.0 {
+            if (0..frames).contains(&centre) {
+                let at = centre as usize * ch;
+                out[..ch].copy_from_slice(&src[at..at + ch]);
+            }
+            return;
+        }
+
+        let radius = self.radius() as i64;
+        let first = (centre - radius + 1).max(0);
+        let last = (centre + radius).min(frames - 1);
+        for i in first..=last {
+            let x = ((i - centre) as f64 - frac) * self.cutoff;
+            let weight = self.kernel(x) * self.cutoff as f32;
+            if weight == 0.0 {
+                continue;
+            }
+            let at = i as usize * ch;
+            for c in 0..ch {
+                out[c] += src[at + c] * weight;
+            }
+        }
+    }
+
+    /// Converts a whole interleaved buffer.
+    pub fn process(&self, src: &[f32]) -> Vec<f32> {
+        let frames = src.len() / self.channels;
+        let out_frames = (frames as f64 / self.step).round() as usize;
+        let mut out = vec![0.0; out_frames * self.channels];
+        for (i, frame) in out.chunks_mut(self.channels).enumerate() {
+            self.frame_at(src, i as f64 * self.step, frame);
+        }
+        out
+    }
+}
+
+const SURROUND_MIX: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Maps one frame between channel layouts. Mono is spread to both front
+/// channels, stereo is averaged down to mono, and 5.1 (in WAVE order) is
+/// folded down to stereo. Other layouts keep the channels they share.
+pub fn remix(input: &[f32], output: &mut [f32]) {
+    let (src, dst) = (input.len(), output.len());
+    output.fill(0.0);
+    match (src, dst) {
+        _ if src == dst => output.copy_from_slice(input),
+        (1, _) => {
+            let n = dst.min(2);
+            output[..n].fill(input[0]);
+        }
+        (_, 1) => output[0] = (input[0] + input[1]) * 0.5,
+        (6, 2) => {
+            let norm = 1.0 / (1.0 + 2.0 * SURROUND_MIX);
+            let centre = input[2] * SURROUND_MIX;
+            output[0] = (input[0] + centre + input[4] * SURROUND_MIX) * norm;
+            output[1] = (input[1] + centre + input[5] * SURROUND_MIX) * norm;
+        }
+        _ => {
+            let n = src.min(dst);
+            output[..n].copy_from_slice(&input[..n]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(rate: u32, hz: f64, amplitude: f64, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (amplitude * (2.0 * PI * hz * i as f64 / rate as f64).sin()) as f32)
+            .collect()
+    }
+
+    /// RMS away from the edges, where the kernel runs off the input.
+    fn rms(samples: &[f32], edge: usize) -> f64 {
+        let middle = &samples[edge..samples.len() - edge];
+        (middle.iter().map(|&s| s as f64 * s as f64).sum::<f64>() / middle.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn upsampling_keeps_a_sine() {
+        let input = sine(44100, 1000.0, 0.5, 44100);
+        let output = Resampler::new(44100, 48000, 1).process(&input);
+        assert_eq!(output.len(), 48000);
+
+        let expected = sine(48000, 1000.0, 0.5, 48000);
+        let edge = 100;
+        let worst = output[edge..48000 - edge]
+            .iter()
+            .zip(&expected[edge..48000 - edge])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        assert!(worst < 1e-3, "off by up to {worst}");
+    }
+
+    #[test]
+    fn downsampling_removes_tones_above_nyquist() {
+        let resampler = Resampler::new(48000, 22050, 1);
+        let edge = resampler.radius();
+
+        let kept = resampler.process(&sine(48000, 1000.0, 0.5, 48000));
+        let kept = rms(&kept, edge);
+        assert!((kept - 0.5 / 2f64.sqrt()).abs() < 0.01, "rms {kept}");
+
+        let removed = resampler.process(&sine(48000, 15000.0, 0.5, 48000));
+        let removed = rms(&removed, edge);
+        assert!(removed < 0.005, "rms {removed}");
+    }
+
+    #[test]
+    fn stereo_frames_stay_paired() {
+        let input: Vec<f32> = (0..1000).flat_map(|_| [0.25, -0.5]).collect();
+        let output = Resampler::new(44100, 48000, 2).process(&input);
+        for frame in output[200..output.len() - 200].chunks_exact(2) {
+            assert!((frame[0] - 0.25).abs() < 1e-3 && (frame[1] + 0.5).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn remixes_between_layouts() {
+        let mut stereo = [0.0; 2];
+        remix(&[0.5], &mut stereo);
+        assert_eq!(stereo, [0.5, 0.5]);
+
+        let mut mono = [0.0];
+        remix(&[0.5, -0.25], &mut mono);
+        assert_eq!(mono, [0.125]);
+
+        // Front left, front right, centre, LFE, rear left, rear right.
+        let norm = 1.0 / (1.0 + 2.0 * SURROUND_MIX);
+        remix(&[1.0, 0.0, 0.0, 1.0, 0.0, 0.0], &mut stereo);
+        assert_eq!(stereo, [norm, 0.0]);
+        remix(&[0.0, 0.0, 1.0, 0.0, 0.0, 0.0], &mut stereo);
+        assert_eq!(stereo, [SURROUND_MIX * norm; 2]);
+        remix(&[0.0, 0.0, 0.0, 0.0, 0.0, 1.0], &mut stereo);
+        assert_eq!(stereo, [0.0, SURROUND_MIX * norm]);
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    f32::consts::PI,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, AtomicU64, Ordering, fence},
+    },
+    time::{Duration, Instant},
+};
+
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+
+/// Mono frames the tap keeps, enough for an FFT's worth behind well over
+/// half a second of output latency.
+const TAP_FRAMES: usize = 1 << 15;
+const FFT_SIZE: usize = 2048;
+/// Frames the amplitude envelope is measured over, about 20 ms.
+const ENVELOPE_FRAMES: usize = 1024;
+const BANDS: usize = 64;
+const MIN_HZ: f32 = 30.0;
+const MAX_HZ: f32 = 16000.0;
+/// How quickly levels fall back once the sound stops, so visuals don't
+/// flicker between updates.
+const RELEASE: Duration = Duration::from_millis(150);
+
+/// The music as it leaves the music bus, mixed to mono, for analysers on
+/// other threads. The output callback writes to it without waiting; a
+/// reader that falls too far behind just sees its copy rejected.
+pub(super) struct SpectrumTap {
+    samples: Box<[AtomicU32]>,
+    /// Frames the callback has started writing, and finished writing.
+    claimed: AtomicU64,
+    written: AtomicU64,
+    rate: AtomicU32,
+    /// Frames between the newest written and what's being heard.
+    latency: AtomicU32,
+}
+
+impl SpectrumTap {
+    pub fn new() -> Self {
+        Self {
+            samples: (0..TAP_FRAMES).map(|_| AtomicU32::new(0)).collect(),
+            claimed: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+            rate: AtomicU32::new(0),
+            latency: AtomicU32::new(0),
+        }
+    }
+
+    pub fn set_rate(&self, rate: u32) {
+        self.rate.store(rate, Ordering::Relaxed);
+    }
+
+    /// Appends interleaved `output`, which reaches the listener `latency`
+    /// after the frames before it.
+    pub fn write(&self, output: &[f32], channels: usize, latency: Duration) {
+        let frames = output.len() / channels;
+        let start = self.written.load(Ordering::Relaxed);
+        self.claimed.store(start + frames as u64, Ordering::Relaxed);
+        fence(Ordering::Release);
+        for (i, frame) in output.chunks_exact(channels).enumerate() {
+            let mono = frame.iter().sum::<f32>() / channels as f32;
+            let slot = (start as usize + i) % TAP_FRAMES;
+            self.samples[slot].store(mono.to_bits(), Ordering::Relaxed);
+        }
+        let rate = self.rate.load(Ordering::Relaxed) as f64;
+        let latency = (latency.as_secs_f64() * rate) as u32;
+        self.latency.store(latency, Ordering::Relaxed);
+        self.written.store(start + frames as u64, Ordering::Release);
+    }
+
+    /// Copies the frames that end where the listener is now, zeros before
+    /// the start. `false` if the callback overwrote them meanwhile, which
+    /// it has once it claims a frame a whole tap past the first one read.
+    fn read_latest(&self, out: &mut [f32]) -> bool {
+        let written = self.written.load(Ordering::Acquire);
+        let latency = self.latency.load(Ordering::Relaxed) as u64;
+        let end = written.saturating_sub(latency);
+        let start = end as i64 - out.len() as i64;
+        for (i, sample) in out.iter_mut().enumerate() {
+            let frame = start + i as i64;
+            *sample = if frame < 0 {
+                0.0
+            } else {
+                let slot = frame as usize % TAP_FRAMES;
+                f32::from_bits(self.samples[slot].load(Ordering::Relaxed))
+            };
+        }
+        fence(Ordering::Acquire);
+        let claimed = self.claimed.load(Ordering::Relaxed);
+        claimed - (start.max(0) as u64) <= TAP_FRAMES as u64
+    }
+}
+
+/// Levels of the music at the current playback time.
+#[derive(Debug, Clone, Default)]
+pub struct Spectrum {
+    /// Magnitudes of log-spaced frequency bands from low to high, where
+    /// a full-scale sine reads 1.
+    pub bands: Vec<f32>,
+    /// RMS level over the last 20 ms or so.
+    pub amplitude: f32,
+}
+
+/// Reads the spectrum of a player's music for visualisers, e.g. once per
+/// frame on the render thread. Levels rise straight away and fall off
+/// smoothly.
+pub struct SpectrumAnalyzer {
+    tap: Arc<SpectrumTap>,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    frames: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    magnitudes: Vec<f32>,
+    range: (f32, f32),
+    release: Duration,
+    spectrum: Spectrum,
+    updated: Option<Instant>,
+}
+
+impl SpectrumAnalyzer {
+    pub(super) fn new(tap: Arc<SpectrumTap>) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(FFT_SIZE);
+        let scratch = vec![Complex::default(); fft.get_inplace_scratch_len()];
+        let window = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FFT_SIZE as f32).cos())
+            .collect();
+        Self {
+            tap,
+            fft,
+            window,
+            frames: vec![0.0; FFT_SIZE],
+            buffer: vec![Complex::default(); FFT_SIZE],
+            scratch,
+            magnitudes: vec![0.0; FFT_SIZE / 2 + 1],
+            range: (MIN_HZ, MAX_HZ),
+            release: RELEASE,
+            spectrum: Spectrum {
+                bands: vec![0.0; BANDS],
+                amplitude: 0.0,
+            },
+            updated: None,
+        }
+    }
+
+    /// Splits `min_hz` to `max_hz` into `bands` bands.
+    pub fn with_bands(mut self, bands: usize, min_hz: f32, max_hz: f32) -> Self {
+        self.spectrum.bands = vec![0.0; bands];
+        self.range = (min_hz, max_hz);
+        self
+    }
+
+    /// How long levels take to fall by about two thirds.
+    pub fn with_release(mut self, release: Duration) -> Self {
+        self.release = release;
+        self
+    }
+
+    pub fn spectrum(&self) -> &Spectrum {
+        &self.spectrum
+    }
+
+    /// Analyses the latest music. If the callback got in the way, the
+    /// levels from last time are kept.
+    pub fn update(&mut self) -> &Spectrum {
+        let now = Instant::now();
+        let elapsed = self.updated.map_or(Duration::MAX, |at| now - at);
+        self.updated = Some(now);
+        let rate = self.tap.rate.load(Ordering::Relaxed);
+        if rate == 0 || !self.tap.read_latest(&mut self.frames) {
+            return &self.spectrum;
+        }
+        let fall = (-elapsed.as_secs_f32() / self.release.as_secs_f32().max(f32::EPSILON)).exp();
+        let smooth = |old: f32, new: f32| new.max(old * fall);
+
+        let recent = &self.frames[FFT_SIZE - ENVELOPE_FRAMES..];
+        let rms = (recent.iter().map(|s| s * s).sum::<f32>() / ENVELOPE_FRAMES as f32).sqrt();
+        self.spectrum.amplitude = smooth(self.spectrum.amplitude, rms);
+
+        for ((out, sample), weight) in self.buffer.iter_mut().zip(&self.frames).zip(&self.window) {
+            *out = Complex::new(sample * weight, 0.0);
+        }
+        self.fft
+            .process_with_scratch(&mut self.buffer, &mut self.scratch);
+        // Scaled by the window's gain, so a sine's peak is its amplitude.
+        let scale = 2.0 / self.window.iter().sum::<f32>();
+        for (magnitude, bin) in self.magnitudes.iter_mut().zip(&self.buffer) {
+            *magnitude = bin.norm() * scale;
+        }
+
+        let bin_hz = rate as f32 / FFT_SIZE as f32;
+        let (min_hz, max_hz) = (self.range.0, self.range.1.min(rate as f32 / 2.0));
+        let count = self.spectrum.bands.len();
+        for (b, band) in self.spectrum.bands.iter_mut().enumerate() {
+            let edge = |i: usize| min_hz * (max_hz / min_hz).powf(i as f32 / count as f32);
+            let (low, high) = (edge(b) / bin_hz, edge(b + 1) / bin_hz);
+            let first = low.ceil() as usize;
+            let last = (high.floor() as usize).min(self.magnitudes.len() - 1);
+            let level = if first <= last {
+                self.magnitudes[first..=last]
+                    .iter()
+                    .copied()
+                    .fold(0.0, f32::max)
+            } else {
+                // Narrower than a bin: read between the two around it.
+                let centre = (low + high) / 2.0;
+                let i = (centre.floor() as usize).min(self.magnitudes.len() - 2);
+                let t = centre - i as f32;
+                self.magnitudes[i] * (1.0 - t) + self.magnitudes[i + 1] * t
+            };
+            *band = smooth(*band, level);
+        }
+        &self.spectrum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    /// Writes a full-scale stereo sine at `hz` in callback-sized buffers,
+    /// heard `latency` frames after it's written.
+    fn tap(hz: f32, frames: usize, latency: usize) -> Arc<SpectrumTap> {
+        let tap = Arc::new(SpectrumTap::new());
+        tap.set_rate(RATE);
+        let latency = Duration::from_secs_f64(latency as f64 / RATE as f64);
+        let output: Vec<f32> = (0..frames)
+            .flat_map(|i| {
+                let s = (2.0 * PI * hz * i as f32 / RATE as f32).sin();
+                [s, s]
+            })
+            .collect();
+        for buffer in output.chunks(1024 * 2) {
+            tap.write(buffer, 2, latency);
+        }
+        tap
+    }
+
+    #[test]
+    fn sine_lights_up_its_band() {
+        // 750 Hz sits on an FFT bin, in band 32 of the default 64. The
+        // window spreads half of it into the next bin, in band 33.
+        let mut analyzer = SpectrumAnalyzer::new(tap(750.0, 8192, 0));
+        let spectrum = analyzer.update();
+        assert!((spectrum.bands[32] - 1.0).abs() < 0.01);
+        for (b, &level) in spectrum.bands.iter().enumerate() {
+            if b != 32 && b != 33 {
+                assert!(level < 0.01, "band {b} reads {level}");
+            }
+        }
+        assert!((spectrum.amplitude - 0.5f32.sqrt()).abs() < 0.01);
+    }
+
+    #[test]
+    fn reads_behind_a_long_latency() {
+        // Further back than half the tap, which is still intact.
+        let mut analyzer = SpectrumAnalyzer::new(tap(750.0, 40000, 20000));
+        assert!((analyzer.update().bands[32] - 1.0).abs() < 0.01);
+    }
+}
@@ -0,0 +1,364 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        OnceLock,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        mpsc::{Receiver, TryRecvError},
+    },
+    thread,
+    time::Duration,
+};
+
+use rtrb::{Consumer, Producer, PushError, RingBuffer};
+use symphonia::core::audio::{Channels, SignalSpec};
+
+use super::decode::{DecodeError, TrackDecoder};
+
+/// Samples queued between the decoder thread and the output callback,
+/// about three seconds of 44.1 kHz stereo.
+const RING_SAMPLES: usize = 1 << 18;
+const RING_MARKERS: usize = 1024;
+/// Samples the callback keeps around the playhead for the resampler.
+const WINDOW_SAMPLES: usize = 1 << 16;
+const IDLE_WAIT: Duration = Duration::from_millis(5);
+/// Audio decoded before the track counts as loaded.
+const PRELOAD_SECS: f64 = 0.5;
+
+/// Describes the samples that follow it in the sample ring.
+enum Marker {
+    /// `samples` samples decoded after seek `epoch`, from `start_frame` on.
+    Chunk {
+        epoch: u64,
+        start_frame: u64,
+        samples: usize,
+    },
+    /// The decoder reached the end of the track after seek `epoch`.
+    End { epoch: u64 },
+}
+
+/// Asks for playback to continue from `secs`. Audio decoded for an older
+/// epoch is thrown away.
+#[derive(Clone, Copy, Default)]
+pub(super) struct Seek {
+    pub epoch: u64,
+    pub secs: f64,
+}
+
+/// What the decoder and the callback publish about the stream, readable
+/// from any thread without locking.
+#[derive(Default)]
+pub(super) struct StreamInfo {
+    rate: AtomicU32,
+    /// Channel bits, or zero until the first chunk is decoded.
+    channels: AtomicU32,
+    /// Length in frames plus one, or zero while unknown.
+    total_frames: AtomicU64,
+    /// Frame the decoder has got to in the file.
+    pub decoded_frames: AtomicU64,
+    /// Frames that must be decoded before the track counts as loaded, or
+    /// zero until the first chunk is decoded.
+    pub ready_frames: AtomicU64,
+    /// Frames decoded ahead of the playhead.
+    pub buffered_frames: AtomicU64,
+    /// Playback is waiting for the decoder to catch up.
+    pub starved: AtomicBool,
+    pub underruns: AtomicU32,
+    /// Corrupt packets the decoder played as silence.
+    pub skipped_packets: AtomicU32,
+    /// The decoder has reached the end of the track.
+    pub finished: AtomicBool,
+    /// Why decoding stopped, if it failed.
+    pub error: OnceLock<DecodeError>,
+}
+
+impl StreamInfo {
+    pub fn spec(&self) -> Option<SignalSpec> {
+        let channels = Channels::from_bits(self.channels.load(Ordering::Acquire))?;
+        if channels.is_empty() {
+            return None;
+        }
+        Some(SignalSpec::new(self.rate.load(Ordering::Relaxed), channels))
+    }
+
+    fn set_spec(&self, spec: SignalSpec) {
+        self.rate.store(spec.rate, Ordering::Relaxed);
+        self.channels.store(spec.channels.bits(), Ordering::Release);
+    }
+
+    pub fn total_frames(&self) -> Option<u64> {
+        self.total_frames.load(Ordering::Acquire).checked_sub(1)
+    }
+
+    fn set_total_frames(&self, frames: u64) {
+        self.total_frames.store(frames + 1, Ordering::Release);
+    }
+}
+
+/// The decoder thread's end of the stream.
+pub(super) struct StreamWriter {
+    samples: Producer<f32>,
+    markers: Producer<Marker>,
+}
+
+impl StreamWriter {
+    /// Queues a marker and its samples, waiting while the rings are full.
+    /// Returns `false` once the reader has gone away.
+    fn push(&mut self, marker: Marker, mut samples: &[f32]) -> bool {
+        let mut marker = Some(marker);
+        while let Some(m) = marker.take() {
+            if let Err(PushError::Full(m)) = self.markers.push(m) {
+                marker = Some(m);
+                if !self.wait() {
+                    return false;
+                }
+            }
+        }
+        while !samples.is_empty() {
+            let n = self.samples.slots().min(samples.len());
+            if n == 0 {
+                if !self.wait() {
+                    return false;
+                }
+                continue;
+            }
+            let (now, rest) = samples.split_at(n);
+            self.samples
+                .write_chunk_uninit(n)
+                .unwrap()
+                .fill_from_iter(now.iter().copied());
+            samples = rest;
+        }
+        true
+    }
+
+    fn wait(&self) -> bool {
+        thread::sleep(IDLE_WAIT);
+        !self.samples.is_abandoned()
+    }
+}
+
+/// The output callback's end of the stream: a window of decoded samples
+/// around the playhead, refilled from the rings without blocking or
+/// allocating.
+pub(super) struct StreamReader {
+    samples: Consumer<f32>,
+    markers: Consumer<Marker>,
+    /// Interleaved samples from `start_frame` on.
+    pub window: Vec<f32>,
+    pub start_frame: u64,
+    epoch: u64,
+    /// Samples of the current chunk still in the ring.
+    remaining: usize,
+    /// The current chunk was decoded before the latest seek.
+    stale: bool,
+    /// The window reaches the end of the track.
+    pub finished: bool,
+}
+
+impl StreamReader {
+    /// Drops the buffered audio; only chunks decoded for `epoch` are used
+    /// from now on.
+    pub fn seek(&mut self, epoch: u64) {
+        self.epoch = epoch;
+        self.window.clear();
+        self.start_frame = 0;
+        self.stale = true;
+        self.finished = false;
+    }
+
+    /// Moves as much decoded audio from the rings into the window as fits.
+    pub fn fill(&mut self) {
+        loop {
+            if self.remaining == 0 {
+                match self.markers.pop() {
+                    Ok(Marker::Chunk {
+                        epoch,
+                        start_frame,
+                        samples,
+                    }) => {
+                        self.stale = epoch != self.epoch;
+                        self.remaining = samples;
+                        if !self.stale && self.window.is_empty() {
+                            self.start_frame = start_frame;
+                        }
+                    }
+                    Ok(Marker::End { epoch }) => {
+                        self.finished |= epoch == self.epoch;
+                        continue;
+                    }
+                    Err(_) => return,
+                }
+            }
+
+            let room = if self.stale {
+                self.remaining
+            } else {
+                let free = self.window.capacity() - self.window.len();
+                free.min(self.remaining)
+            };
+            let n = room.min(self.samples.slots());
+            if n == 0 {
+                return;
+            }
+            let chunk = self.samples.read_chunk(n).unwrap();
+            if !self.stale {
+                let (first, second) = chunk.as_slices();
+                self.window.extend_from_slice(first);
+                self.window.extend_from_slice(second);
+            }
+            chunk.commit_all();
+            self.remaining -= n;
+        }
+    }
+
+    pub fn end_frame(&self, channels: usize) -> u64 {
+        self.start_frame + (self.window.len() / channels) as u64
+    }
+
+    /// Frames decoded ahead of `frame`, in the window and still in the ring.
+    pub fn frames_ahead(&self, frame: u64, channels: usize) -> u64 {
+        let queued = if self.stale { 0 } else { self.samples.slots() };
+        self.end_frame(channels).saturating_sub(frame) + (queued / channels) as u64
+    }
+
+    /// Drops samples before `frame`, once they can no longer be played.
+    pub fn release_before(&mut self, frame: u64, channels: usize) {
+        let buffered = (self.window.len() / channels) as u64;
+        let frames = frame.saturating_sub(self.start_frame).min(buffered);
+        self.window.drain(..frames as usize * channels);
+        self.start_frame += frames;
+    }
+}
+
+pub(super) fn channel() -> (StreamWriter, StreamReader) {
+    let (samples, sample_reader) = RingBuffer::new(RING_SAMPLES);
+    let (markers, marker_reader) = RingBuffer::new(RING_MARKERS);
+    let writer = StreamWriter { samples, markers };
+    let reader = StreamReader {
+        samples: sample_reader,
+        markers: marker_reader,
+        window: Vec::with_capacity(WINDOW_SAMPLES),
+        start_frame: 0,
+        epoch: 0,
+        remaining: 0,
+        stale: false,
+        finished: false,
+    };
+    (writer, reader)
+}
+
+/// Frames to decode before playback can start. Capped at half the ring,
+/// so the decoder can always get that far without waiting on the callback.
+fn preload_frames(spec: SignalSpec) -> u64 {
+    let ring_frames = RING_SAMPLES / spec.channels.count() / 2;
+    ((PRELOAD_SECS * spec.rate as f64) as u64).min(ring_frames as u64)
+}
+
+/// Decoder thread body: decodes ahead of the playhead as far as the rings
+/// allow and carries out seeks, until the player goes away or decoding
+/// fails. `on_spec` runs with the format of the first chunk, and
+/// `on_loaded` once enough audio is queued to start playing.
+pub(super) fn run_decoder(
+    path: PathBuf,
+    mut writer: StreamWriter,
+    info: &StreamInfo,
+    seeks: Receiver<Seek>,
+    on_spec: impl FnOnce(SignalSpec),
+    on_loaded: impl FnOnce(),
+) -> Result<(), DecodeError> {
+    let mut decoder = TrackDecoder::open(&path)?;
+    if let Some(total) = decoder.total_frames() {
+        info.set_total_frames(total);
+    }
+    let mut on_spec = Some(on_spec);
+    let mut on_loaded = Some(on_loaded);
+    let mut epoch = 0;
+    let mut end_frame = 0;
+    let mut finished = false;
+
+    loop {
+        // Once at the end, there is nothing to do until the next seek.
+        let next = if finished {
+            match seeks.recv() {
+                Ok(seek) => Some(seek),
+                Err(_) => return Ok(()),
+            }
+        } else {
+            match seeks.try_recv() {
+                Ok(seek) => Some(seek),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        };
+        if let Some(seek) = next.into_iter().chain(seeks.try_iter()).last() {
+            epoch = seek.epoch;
+            if !decoder.seek(seek.secs) {
+                // Start over and decode up to the target instead.
+                let reopened = TrackDecoder::open(&path)
+                    .and_then(|mut decoder| decoder.skip_to(seek.secs).map(|()| decoder));
+                match reopened {
+                    Ok(reopened) => decoder = reopened,
+                    Err(e) => {
+                        writer.push(Marker::End { epoch }, &[]);
+                        return Err(e);
+                    }
+                }
+            }
+            finished = false;
+            info.finished.store(false, Ordering::Relaxed);
+        }
+
+        let chunk = match decoder.next_chunk() {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                writer.push(Marker::End { epoch }, &[]);
+                return Err(e);
+            }
+        };
+        match chunk {
+            Some(chunk) => {
+                if info.spec().is_none() {
+                    info.ready_frames
+                        .store(preload_frames(chunk.spec), Ordering::Relaxed);
+                    info.set_spec(chunk.spec);
+                    if let Some(f) = on_spec.take() {
+                        f(chunk.spec);
+                    }
+                }
+                let frames = chunk.samples.len() / chunk.spec.channels.count();
+                end_frame = chunk.start_frame + frames as u64;
+                let marker = Marker::Chunk {
+                    epoch,
+                    start_frame: chunk.start_frame,
+                    samples: chunk.samples.len(),
+                };
+                if !writer.push(marker, chunk.samples) {
+                    return Ok(());
+                }
+                info.decoded_frames.store(end_frame, Ordering::Relaxed);
+                info.skipped_packets
+                    .store(decoder.skipped_packets(), Ordering::Relaxed);
+                let ready = info.ready_frames.load(Ordering::Relaxed);
+                if let Some(f) = on_loaded.take_if(|_| end_frame >= ready) {
+                    f();
+                }
+            }
+            None => {
+                finished = true;
+                if info.total_frames().is_none() {
+                    info.set_total_frames(end_frame);
+                }
+                info.finished.store(true, Ordering::Relaxed);
+                if !writer.push(Marker::End { epoch }, &[]) {
+                    return Ok(());
+                }
+                // A track shorter than the preload is loaded once it's all
+                // decoded.
+                if let Some(f) = on_loaded.take() {
+                    info.ready_frames.store(end_frame, Ordering::Relaxed);
+                    f();
+                }
+            }
+        }
+    }
+}
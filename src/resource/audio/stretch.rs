@@ -0,0 +1,142 @@
+use std::f64::consts::PI;
+
+use super::resample::Resampler;
+
+/// Output frames per grain. Grains overlap by half.
+const GRAIN: usize = 2048;
+const HOP: usize = GRAIN / 2;
+/// How far, in source frames, a grain may move to line up with the last.
+const TOLERANCE: i64 = 512;
+/// Offsets tried and frames compared are spaced out to keep the search cheap.
+const SEARCH_STRIDE: usize = 2;
+const CORRELATION_STRIDE: usize = 4;
+
+/// Changes tempo without changing pitch using WSOLA: grains of the track
+/// at normal speed are overlap-added at a fixed hop, each nudged to where
+/// it best continues the one before, so the waveform stays in phase.
+///
+/// Grains are read through the resampler, so they come out at the output
+/// rate directly.
+pub(super) struct TimeStretch {
+    channels: usize,
+    window: Vec<f32>,
+    /// One grain of overlap-added output. The first `HOP` frames are done.
+    acc: Vec<f32>,
+    /// Finished frames already handed out.
+    used: usize,
+    /// Track frame the previous grain started at.
+    last_start: Option<f64>,
+    frame: Vec<f32>,
+}
+
+impl TimeStretch {
+    pub fn new(channels: usize) -> Self {
+        // A periodic Hann window sums to one at half overlap.
+        let window = (0..GRAIN)
+            .map(|i| (0.5 - 0.5 * (2.0 * PI * i as f64 / GRAIN as f64).cos()) as f32)
+            .collect();
+        Self {
+            channels,
+            window,
+            acc: vec![0.0; GRAIN * channels],
+            used: HOP,
+            last_start: None,
+            frame: vec![0.0; channels],
+        }
+    }
+
+    /// Starts over after a jump, fading the next grain in from silence.
+    pub fn reset(&mut self) {
+        self.acc.fill(0.0);
+        self.used = HOP;
+        self.last_start = None;
+    }
+
+    /// Track frames a grain may read behind and ahead of the playhead when
+    /// playing at `rate` with `step` track frames per output frame.
+    pub fn reach(step: f64, rate: f64) -> (f64, f64) {
+        let shift = HOP as f64 * step * (rate - 1.0);
+        let behind = (-shift).max(0.0) + TOLERANCE as f64;
+        let ahead = shift.max(0.0) + GRAIN as f64 * step + TOLERANCE as f64;
+        (behind, ahead)
+    }
+
+    /// Writes the next output frame into `out`. `pos` is the playhead in
+    /// track frames, and `src` holds the track from frame `offset` on.
+    pub fn next_frame(
+        &mut self,
+        resampler: &Resampler,
+        src: &[f32],
+        offset: f64,
+        pos: f64,
+        rate: f64,
+        out: &mut [f32],
+    ) {
+        if self.used == HOP {
+            self.next_grain(resampler, src, offset, pos, rate);
+        }
+        let at = self.used * self.channels;
+        out.copy_from_slice(&self.acc[at..at + self.channels]);
+        self.used += 1;
+    }
+
+    fn next_grain(&mut self, resampler: &Resampler, src: &[f32], offset: f64, pos: f64, rate: f64) {
+        let ch = self.channels;
+        let step = resampler.step();
+        self.acc.copy_within(HOP * ch.., 0);
+        self.acc[(GRAIN - HOP) * ch..].fill(0.0);
+
+        // Centre the grain on where the playhead will be halfway through it.
+        let nominal = pos + HOP as f64 * step * (rate - 1.0);
+        let start = match self.last_start {
+            Some(last) => self.best_start(src, offset, last + HOP as f64 * step, nominal, step),
+            None => nominal,
+        };
+
+        for (j, w) in self.window.iter().enumerate() {
+            resampler.frame_at(src, start - offset + j as f64 * step, &mut self.frame);
+            let acc = &mut self.acc[j * ch..(j + 1) * ch];
+            for (a, s) in acc.iter_mut().zip(&self.frame) {
+                *a += s * w;
+            }
+        }
+        self.last_start = Some(start);
+        self.used = 0;
+    }
+
+    /// The start near `nominal` whose audio best matches `target`, where
+    /// the previous grain would have carried on.
+    fn best_start(&self, src: &[f32], offset: f64, target: f64, nominal: f64, step: f64) -> f64 {
+        let ch = self.channels;
+        let mono = |frame: i64| -> f32 {
+            if frame < 0 {
+                return 0.0;
+            }
+            let at = frame as usize * ch;
+            src.get(at..at + ch).map_or(0.0, |f| f.iter().sum())
+        };
+        let len = (HOP as f64 * step) as i64;
+        let target = (target - offset).round() as i64;
+        let nominal_frame = (nominal - offset).round() as i64;
+
+        let mut best = (f32::MIN, 0);
+        for delta in (-TOLERANCE..=TOLERANCE).step_by(SEARCH_STRIDE) {
+            let candidate = nominal_frame + delta;
+            let (mut corr, mut energy) = (0.0, 0.0);
+            for k in (0..len).step_by(CORRELATION_STRIDE) {
+                let b = mono(candidate + k);
+                corr += mono(target + k) * b;
+                energy += b * b;
+            }
+            let score = if energy > 0.0 {
+                corr / energy.sqrt()
+            } else {
+                0.0
+            };
+            if score > best.0 {
+                best = (score, delta);
+            }
+        }
+        nominal + best.1 as f64
+    }
+}
@@ -0,0 +1,389 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use super::decode::{DecodeError, DecodedAudio, decode_all};
+use crate::resource::file_stamp;
+
+/// Frames summarised by each peak of the finest level, about 6 ms at
+/// 44.1 kHz.
+const BASE_FRAMES: usize = 256;
+/// Each level summarises this many peaks of the one below.
+const LEVEL_FACTOR: usize = 4;
+const CACHE_MAGIC: &[u8; 4] = b"RPK1";
+/// Magic, source mtime and size, sample rate, frames, peak count.
+const HEADER_LEN: usize = 4 + 8 + 8 + 4 + 8 + 8;
+
+/// Range and loudness of a stretch of audio, mixed down to mono.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Peak {
+    pub min: f32,
+    pub max: f32,
+    pub rms: f32,
+}
+
+impl Peak {
+    fn merge(peaks: &[Peak]) -> Peak {
+        if peaks.is_empty() {
+            return Peak::default();
+        }
+        let (mut min, mut max, mut squares) = (f32::MAX, f32::MIN, 0.0);
+        for peak in peaks {
+            min = min.min(peak.min);
+            max = max.max(peak.max);
+            squares += peak.rms * peak.rms;
+        }
+        Peak {
+            min,
+            max,
+            rms: (squares / peaks.len() as f32).sqrt(),
+        }
+    }
+}
+
+/// Peaks over fixed-size runs of frames.
+#[derive(Debug, Clone)]
+pub struct PeakLevel {
+    pub frames_per_peak: usize,
+    pub peaks: Vec<Peak>,
+}
+
+/// Peak tables of a track at several resolutions, for drawing it at any
+/// zoom without going back to the samples.
+#[derive(Debug, Clone)]
+pub struct Waveform {
+    pub rate: u32,
+    pub frames: u64,
+    /// Finest first.
+    pub levels: Vec<PeakLevel>,
+}
+
+impl Waveform {
+    pub fn from_audio(audio: &DecodedAudio) -> Self {
+        let channels = audio.spec.channels.count();
+        let base = audio
+            .samples
+            .chunks(BASE_FRAMES * channels)
+            .map(|run| {
+                let (mut min, mut max, mut squares) = (f32::MAX, f32::MIN, 0.0);
+                for frame in run.chunks_exact(channels) {
+                    let mono = frame.iter().sum::<f32>() / channels as f32;
+                    min = min.min(mono);
+                    max = max.max(mono);
+                    squares += mono * mono;
+                }
+                let frames = (run.len() / channels).max(1);
+                Peak {
+                    min,
+                    max,
+                    rms: (squares / frames as f32).sqrt(),
+                }
+            })
+            .collect();
+        Self::from_base(audio.spec.rate, audio.frames() as u64, base)
+    }
+
+    /// Builds the coarser levels on top of the finest one.
+    fn from_base(rate: u32, frames: u64, base: Vec<Peak>) -> Self {
+        let mut levels = vec![PeakLevel {
+            frames_per_peak: BASE_FRAMES,
+            peaks: base,
+        }];
+        while let Some(last) = levels.last().filter(|l| l.peaks.len() > 1) {
+            let level = PeakLevel {
+                frames_per_peak: last.frames_per_peak * LEVEL_FACTOR,
+                peaks: last.peaks.chunks(LEVEL_FACTOR).map(Peak::merge).collect(),
+            };
+            levels.push(level);
+        }
+        Self {
+            rate,
+            frames,
+            levels,
+        }
+    }
+
+    /// Decodes `audio` for its peaks, or reads them from the cache file
+    /// next to it if the track hasn't changed since. A cache that can't be
+    /// written is skipped.
+    pub fn load_or_build(audio: &Path) -> Result<Self, DecodeError> {
+        let cache = cache_path(audio);
+        let stamp = file_stamp(audio).map_err(|e| DecodeError::Open(e.kind()))?;
+        if let Ok(waveform) = Self::read_cache(&cache, stamp) {
+            return Ok(waveform);
+        }
+        let waveform = Self::from_audio(&decode_all(audio)?);
+        let _ = waveform.write_cache(&cache, stamp);
+        Ok(waveform)
+    }
+
+    pub fn duration_ms(&self) -> f64 {
+        self.frames as f64 / self.rate as f64 * 1000.0
+    }
+
+    /// One peak per column for `columns` equal slices of `start_ms` to
+    /// `end_ms`, from the coarsest level that still resolves a column.
+    /// Slices outside the track are silent.
+    pub fn peaks_in(&self, start_ms: f64, end_ms: f64, columns: usize) -> Vec<Peak> {
+        let to_frame = |ms: f64| ms / 1000.0 * self.rate as f64;
+        let start = to_frame(start_ms);
+        let per_column = (to_frame(end_ms) - start) / columns.max(1) as f64;
+        let level = self
+            .levels
+            .iter()
+            .rev()
+            .find(|l| l.frames_per_peak as f64 <= per_column)
+            .unwrap_or(&self.levels[0]);
+
+        let fpp = level.frames_per_peak as f64;
+        (0..columns)
+            .map(|c| {
+                let from = start + c as f64 * per_column;
+                let to = from + per_column;
+                if to <= 0.0 {
+                    return Peak::default();
+                }
+                let first = (from.max(0.0) / fpp).floor() as usize;
+                let last = ((to / fpp).ceil() as usize).max(first + 1);
+                let first = first.min(level.peaks.len());
+                Peak::merge(&level.peaks[first..last.min(level.peaks.len())])
+            })
+            .collect()
+    }
+
+    /// Draws `start_ms` to `end_ms` into a `width` by `height` image, the
+    /// peak range behind the RMS level, centred on the zero line.
+    pub fn render(
+        &self,
+        start_ms: f64,
+        end_ms: f64,
+        width: usize,
+        height: usize,
+        colors: &WaveformColors,
+    ) -> Image {
+        let mut image = Image::new(width, height, colors.background);
+        if height == 0 {
+            return image;
+        }
+        let half = (height - 1) as f32 / 2.0;
+        let row = |amplitude: f32| ((1.0 - amplitude.clamp(-1.0, 1.0)) * half).round() as usize;
+        for (x, peak) in self.peaks_in(start_ms, end_ms, width).iter().enumerate() {
+            if peak.max < peak.min {
+                continue;
+            }
+            image.fill_column(x, row(peak.max), row(peak.min), colors.peak);
+            image.fill_column(x, row(peak.rms), row(-peak.rms), colors.rms);
+        }
+        image
+    }
+
+    fn read_cache(path: &Path, stamp: (u64, u64)) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        let invalid = || io::Error::from(ErrorKind::InvalidData);
+        let (header, body) = data.split_at_checked(HEADER_LEN).ok_or_else(invalid)?;
+        let u64_at = |at: usize| u64::from_le_bytes(header[at..at + 8].try_into().unwrap());
+        if &header[..4] != CACHE_MAGIC || (u64_at(4), u64_at(12)) != stamp {
+            return Err(invalid());
+        }
+        let rate = u32::from_le_bytes(header[20..24].try_into().unwrap());
+        let frames = u64_at(24);
+        let count = u64_at(32) as usize;
+        if body.len() != count * 12 {
+            return Err(invalid());
+        }
+
+        let float = |b: &[u8]| f32::from_le_bytes(b.try_into().unwrap());
+        let base = body
+            .chunks_exact(12)
+            .map(|p| Peak {
+                min: float(&p[0..4]),
+                max: float(&p[4..8]),
+                rms: float(&p[8..12]),
+            })
+            .collect();
+        Ok(Self::from_base(rate, frames, base))
+    }
+
+    /// Only the finest level is stored; the rest are quick to rebuild.
+    fn write_cache(&self, path: &Path, stamp: (u64, u64)) -> io::Result<()> {
+        let base = &self.levels[0].peaks;
+        let mut data = Vec::with_capacity(HEADER_LEN + base.len() * 12);
+        data.extend_from_slice(CACHE_MAGIC);
+        data.extend_from_slice(&stamp.0.to_le_bytes());
+        data.extend_from_slice(&stamp.1.to_le_bytes());
+        data.extend_from_slice(&self.rate.to_le_bytes());
+        data.extend_from_slice(&self.frames.to_le_bytes());
+        data.extend_from_slice(&(base.len() as u64).to_le_bytes());
+        for peak in base {
+            for value in [peak.min, peak.max, peak.rms] {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        fs::write(path, data)
+    }
+}
+
+impl DecodedAudio {
+    pub fn waveform(&self) -> Waveform {
+        Waveform::from_audio(self)
+    }
+}
+
+/// Where the peaks of `audio` are cached: `audio.mp3.peaks` beside it.
+pub fn cache_path(audio: &Path) -> PathBuf {
+    let mut name = audio.as_os_str().to_owned();
+    name.push(".peaks");
+    PathBuf::from(name)
+}
+
+/// RGBA colours of a rendered waveform.
+#[derive(Debug, Clone, Copy)]
+pub struct WaveformColors {
+    pub background: [u8; 4],
+    pub peak: [u8; 4],
+    pub rms: [u8; 4],
+}
+
+impl Default for WaveformColors {
+    fn default() -> Self {
+        Self {
+            background: [0, 0, 0, 0],
+            peak: [110, 140, 200, 255],
+            rms: [200, 220, 255, 255],
+        }
+    }
+}
+
+/// An RGBA8 image, row by row from the top.
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, color: [u8; 4]) -> Self {
+        Self {
+            width,
+            height,
+            pixels: color.repeat(width * height),
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let at = (y * self.width + x) * 4;
+        self.pixels[at..at + 4].try_into().unwrap()
+    }
+
+    /// Colours rows `top..=bottom` of column `x`.
+    fn fill_column(&mut self, x: usize, top: usize, bottom: usize, color: [u8; 4]) {
+        for y in top..=bottom.min(self.height - 1) {
+            let at = (y * self.width + x) * 4;
+            self.pixels[at..at + 4].copy_from_slice(&color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use symphonia::core::audio::{Channels, SignalSpec};
+
+    use super::*;
+    use crate::test_util::{TempDir, write_wav};
+
+    /// Sixteen base runs at 1 kHz, run `i` alternating between `±(i + 1) / 16`.
+    fn runs() -> Waveform {
+        let samples = (0..16 * BASE_FRAMES)
+            .map(|i| {
+                let level = (i / BASE_FRAMES + 1) as f32 / 16.0;
+                if i % 2 == 0 { level } else { -level }
+            })
+            .collect();
+        let audio = DecodedAudio {
+            spec: SignalSpec::new(1000, Channels::FRONT_LEFT),
+            samples,
+        };
+        Waveform::from_audio(&audio)
+    }
+
+    #[test]
+    fn columns_use_the_coarsest_level_that_fits() {
+        let waveform = runs();
+        assert_eq!(waveform.levels.len(), 3);
+        assert_eq!(waveform.levels[0].peaks[3].max, 0.25);
+        assert_eq!(waveform.levels[1].peaks[0].min, -0.25);
+
+        let end = (16 * BASE_FRAMES) as f64;
+        for (columns, level) in [(16, 0), (4, 1), (1, 2)] {
+            let peaks = waveform.peaks_in(0.0, end, columns);
+            assert_eq!(peaks, waveform.levels[level].peaks);
+        }
+        // Finer than the base level still reads from it.
+        let peaks = waveform.peaks_in(0.0, 256.0, 4);
+        assert!(peaks.iter().all(|p| *p == waveform.levels[0].peaks[0]));
+    }
+
+    #[test]
+    fn columns_outside_the_track_are_silent() {
+        let waveform = runs();
+        let per_run = BASE_FRAMES as f64;
+        let peaks = waveform.peaks_in(-per_run, per_run, 2);
+        assert_eq!(peaks, [Peak::default(), waveform.levels[0].peaks[0]]);
+        let end = (16 * BASE_FRAMES) as f64;
+        let peaks = waveform.peaks_in(end, end + per_run * 2.0, 2);
+        assert_eq!(peaks, [Peak::default(); 2]);
+    }
+
+    #[test]
+    fn render_draws_rms_over_the_peak_range() {
+        let base = vec![
+            Peak {
+                min: -1.0,
+                max: 1.0,
+                rms: 0.5,
+            },
+            Peak {
+                min: 0.0,
+                max: 0.5,
+                rms: 0.25,
+            },
+        ];
+        let waveform = Waveform::from_base(1000, 2 * BASE_FRAMES as u64, base);
+        let colors = WaveformColors::default();
+        let end = (2 * BASE_FRAMES) as f64;
+        let image = waveform.render(0.0, end, 2, 5, &colors);
+
+        // Rows run from +1 at the top to -1 at the bottom.
+        let (bg, peak, rms) = (colors.background, colors.peak, colors.rms);
+        let column = |x| (0..5).map(|y| image.pixel(x, y)).collect::<Vec<_>>();
+        assert_eq!(column(0), [peak, rms, rms, rms, peak]);
+        assert_eq!(column(1), [bg, peak, rms, rms, bg]);
+    }
+
+    #[test]
+    fn cache_is_used_until_the_track_changes() {
+        let dir = TempDir::new("waveform_cache");
+        let path = dir.join("track.wav");
+        write_wav(&path, 1000, 1, &[0.5; 4 * BASE_FRAMES]);
+
+        let built = Waveform::load_or_build(&path).unwrap();
+        assert!(cache_path(&path).exists());
+        let cached = Waveform::read_cache(&cache_path(&path), file_stamp(&path).unwrap());
+        let cached = cached.unwrap();
+        assert_eq!((cached.rate, cached.frames), (1000, 4 * BASE_FRAMES as u64));
+        assert_eq!(cached.levels.len(), built.levels.len());
+        for (cached, built) in cached.levels.iter().zip(&built.levels) {
+            assert_eq!(cached.peaks, built.peaks);
+        }
+
+        // A different length makes the cache stale, so it's rebuilt.
+        write_wav(&path, 1000, 1, &[0.5; 2 * BASE_FRAMES]);
+        let stamp = file_stamp(&path).unwrap();
+        assert!(Waveform::read_cache(&cache_path(&path), stamp).is_err());
+        let rebuilt = Waveform::load_or_build(&path).unwrap();
+        assert_eq!(rebuilt.frames, 2 * BASE_FRAMES as u64);
+    }
+}
@@ -1,2 +1,16 @@
 pub mod osufile;
-pub mod audio;
\ No newline at end of file
+pub mod osz;
+pub mod audio;
+
+use std::{fs, io, path::Path, time::UNIX_EPOCH};
+
+/// Modification time in seconds and size of a file, to tell when what was
+/// cached about it is stale.
+pub fn file_stamp(path: &Path) -> io::Result<(u64, u64)> {
+    let meta = fs::metadata(path)?;
+    let mtime = meta
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    Ok((mtime, meta.len()))
+}
@@ -1,7 +1,10 @@
 use std::{
-    collections::HashMap, fs::File, io::{BufRead, BufReader}, path::Path
+    collections::HashMap, fs::File, io::{BufRead, BufReader, Read}, path::Path
 };
 
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
 #[derive(Debug, Default)]
 pub struct OsuFile {
     pub general: General,
@@ -10,13 +13,21 @@ pub struct OsuFile {
     pub timing_points: Vec<TimingPoint>,
     pub hit_objects: Vec<HitObject>,
     pub colours: Colours,
+    /// Lowercase hex MD5 of the raw file bytes, which replays and scores
+    /// use to identify the beatmap.
+    pub md5: String,
+    pub sha256: String,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct General {
     pub audio_filename: String,
+    /// Silence before the track starts, in milliseconds.
     pub audio_lead_in: i32,
+    /// Where song select previews start, in milliseconds, or -1 if unset.
     pub preview_time: i32,
+    /// Speed of the countdown before the first object: 0 for none, 1 for
+    /// normal, 2 for half and 3 for double speed.
     pub countdown: i32,
     pub sample_set: String,
     pub stack_leniency: f32,
@@ -39,7 +50,7 @@ pub struct Metadata {
     pub beatmap_set_id: i32,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Difficulty {
     pub hp_drain_rate: f32,
     pub circle_size: f32,
@@ -90,6 +101,22 @@ pub struct HitObject {
     pub extras: String,
 }
 
+impl HitObject {
+    /// Time at which the object is finished. Spinners carry their end time
+    /// in the extras; sliders are not yet measured and end at their head.
+    pub fn end_time(&self) -> i32 {
+        match self.shape {
+            HitObjectShape::Spinner => self
+                .extras
+                .split(',')
+                .next()
+                .and_then(|t| t.parse().ok())
+                .unwrap_or(self.time),
+            _ => self.time,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Colours {
     pub combos: Vec<(u8, u8, u8)>,
@@ -105,6 +132,22 @@ impl Default for Colours {
     }
 }
 
+impl Default for General {
+    fn default() -> Self {
+        General {
+            audio_filename: String::new(),
+            audio_lead_in: 0,
+            preview_time: -1,
+            countdown: 1,
+            sample_set: String::new(),
+            stack_leniency: 0.0,
+            mode: 0,
+            letterbox_in_breaks: false,
+            widescreen_storyboard: false,
+        }
+    }
+}
+
 trait ParseKeyValue {
     fn set_field(&mut self, key: &str, value: &str);
 }
@@ -113,6 +156,10 @@ impl ParseKeyValue for General {
     fn set_field(&mut self, key: &str, value: &str) {
         match key {
             "AudioFilename" => self.audio_filename = value.to_string(),
+            "AudioLeadIn" => self.audio_lead_in = value.parse().unwrap_or(0),
+            "PreviewTime" => self.preview_time = value.parse().unwrap_or(-1),
+            "Countdown" => self.countdown = value.parse().unwrap_or(1),
+            "SampleSet" => self.sample_set = value.to_string(),
             "Mode" => self.mode = value.parse().unwrap_or(0),
             _ => {}
         }
@@ -123,9 +170,15 @@ impl ParseKeyValue for Metadata {
     fn set_field(&mut self, key: &str, value: &str) {
         match key {
             "Title" => self.title = value.to_string(),
+            "TitleUnicode" => self.title_unicode = value.to_string(),
             "Artist" => self.artist = value.to_string(),
+            "ArtistUnicode" => self.artist_unicode = value.to_string(),
             "Creator" => self.creator = value.to_string(),
             "Version" => self.version = value.to_string(),
+            "Source" => self.source = value.to_string(),
+            "Tags" => self.tags = value.to_string(),
+            "BeatmapID" => self.beatmap_id = value.parse().unwrap_or(0),
+            "BeatmapSetID" => self.beatmap_set_id = value.parse().unwrap_or(-1),
             _ => {}
         }
     }
@@ -147,8 +200,21 @@ impl ParseKeyValue for Difficulty {
 
 pub fn parse_osu(path: &Path) -> OsuFile {
     let file = File::open(path).unwrap();
-    let reader = BufReader::new(file);
+    parse_osu_reader(file)
+}
 
+/// Parses a beatmap from any reader, such as a file inside an archive.
+pub fn parse_osu_reader<R: Read>(mut reader: R) -> OsuFile {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).unwrap();
+
+    let mut osu = parse_sections(BufReader::new(bytes.as_slice()));
+    osu.md5 = format!("{:x}", Md5::digest(&bytes));
+    osu.sha256 = format!("{:x}", Sha256::digest(&bytes));
+    osu
+}
+
+fn parse_sections<R: BufRead>(reader: R) -> OsuFile {
     let mut osu = OsuFile::default();
     let mut section = String::new();
 
@@ -183,9 +249,14 @@ fn parse_key_value<T: ParseKeyValue>(line: &str, target: &mut T) {
     }
 }
 
+/// Field `i` of a comma-separated line, empty if the line is cut short.
+fn field<'a>(parts: &[&'a str], i: usize) -> &'a str {
+    parts.get(i).map_or("", |p| p.trim())
+}
+
 fn parse_hit_object(line: &str) -> HitObject {
     let parts: Vec<&str> = line.split(',').collect();
-    let obj_type_num: u32 = parts[3].parse().unwrap_or(0);
+    let obj_type_num: u32 = field(&parts, 3).parse().unwrap_or(0);
     let obj_type = HitObjectType::from_bits_truncate(obj_type_num);
 
     let shape = if obj_type.contains(HitObjectType::CIRCLE) {
@@ -199,29 +270,30 @@ fn parse_hit_object(line: &str) -> HitObject {
     };
 
     HitObject {
-        x: parts[0].parse().unwrap_or(0),
-        y: parts[1].parse().unwrap_or(0),
-        time: parts[2].parse().unwrap_or(0),
+        x: field(&parts, 0).parse().unwrap_or(0),
+        y: field(&parts, 1).parse().unwrap_or(0),
+        time: field(&parts, 2).parse().unwrap_or(0),
         obj_type,
         shape,
-        hit_sound: parts[4].parse().unwrap_or(0),
-        extras: parts[5..].join(","),
+        hit_sound: field(&parts, 4).parse().unwrap_or(0),
+        extras: parts.get(5..).map_or(String::new(), |rest| rest.join(",")),
     }
 }
 
-
+/// Old file formats leave out the trailing fields, which then take the
+/// defaults of later versions.
 fn parse_timing_point(line: &str) -> TimingPoint {
     let parts: Vec<&str> = line.split(',').collect();
 
     TimingPoint {
-        offset: parts[0].parse().unwrap_or(0.0),
-        ms_per_beat: parts[1].parse().unwrap_or(0.0),
-        meter: parts[2].parse().unwrap_or(4),
-        sample_type: parts[3].parse().unwrap_or(0),
-        sample_set: parts[4].parse().unwrap_or(0),
-        volume: parts[5].parse().unwrap_or(100),
-        uninherited: parts[6].parse::<i32>().unwrap_or(1) == 1,
-        effects: parts[7].parse().unwrap_or(0),
+        offset: field(&parts, 0).parse().unwrap_or(0.0),
+        ms_per_beat: field(&parts, 1).parse().unwrap_or(0.0),
+        meter: field(&parts, 2).parse().unwrap_or(4),
+        sample_type: field(&parts, 3).parse().unwrap_or(0),
+        sample_set: field(&parts, 4).parse().unwrap_or(0),
+        volume: field(&parts, 5).parse().unwrap_or(100),
+        uninherited: field(&parts, 6).parse::<i32>().unwrap_or(1) == 1,
+        effects: field(&parts, 7).parse().unwrap_or(0),
     }
 }
 
@@ -243,3 +315,36 @@ fn parse_colour(line: &str, colours: &mut Colours) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_lines_take_defaults() {
+        let osu = parse_osu_reader(
+            "osu file format v5\n\n[TimingPoints]\n1000,500\n1500\n\n[HitObjects]\n64,128,2000,1\n64\n".as_bytes(),
+        );
+        assert_eq!(osu.timing_points.len(), 2);
+        let tp = &osu.timing_points[0];
+        assert_eq!((tp.offset, tp.ms_per_beat, tp.meter), (1000.0, 500.0, 4));
+        assert_eq!(tp.volume, 100);
+        assert!(tp.uninherited);
+
+        assert_eq!(osu.hit_objects.len(), 2);
+        let ho = &osu.hit_objects[0];
+        assert_eq!((ho.x, ho.y, ho.time, ho.hit_sound), (64, 128, 2000, 0));
+        assert!(matches!(ho.shape, HitObjectShape::Circle));
+        assert!(ho.extras.is_empty());
+        assert!(matches!(osu.hit_objects[1].shape, HitObjectShape::Unknown));
+    }
+
+    #[test]
+    fn missing_general_keys_use_osu_defaults() {
+        let osu =
+            parse_osu_reader("osu file format v14\n\n[General]\nAudioFilename: a.mp3\n".as_bytes());
+        assert_eq!(osu.general.preview_time, -1);
+        assert_eq!(osu.general.countdown, 1);
+        assert_eq!(osu.general.audio_lead_in, 0);
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use zip::{CompressionMethod, ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use crate::resource::osufile::{OsuFile, parse_osu_reader};
+
+/// A beatmap set packed as an `.osz` (zip) archive.
+///
+/// Besides extracting, files can be read straight out of the archive, so
+/// a set can be played without unpacking it first.
+pub struct OszArchive<R: Read + Seek> {
+    archive: ZipArchive<R>,
+}
+
+impl OszArchive<File> {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::from_reader(File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek> OszArchive<R> {
+    pub fn from_reader(reader: R) -> io::Result<Self> {
+        Ok(Self {
+            archive: ZipArchive::new(reader)?,
+        })
+    }
+
+    /// Names of every file in the archive.
+    pub fn file_names(&self) -> Vec<String> {
+        self.archive.file_names().map(str::to_string).collect()
+    }
+
+    /// Names of the `.osu` difficulties in the archive.
+    pub fn difficulties(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .archive
+            .file_names()
+            .filter(|name| name.to_ascii_lowercase().ends_with(".osu"))
+            .map(str::to_string)
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Reads a file such as the audio track or a hitsound from the archive.
+    /// Names are matched case-insensitively, like osu! does on Windows.
+    pub fn read_file(&mut self, name: &str) -> io::Result<Vec<u8>> {
+        let index = (0..self.archive.len())
+            .find(|&i| {
+                self.archive
+                    .name_for_index(i)
+                    .is_some_and(|n| n.eq_ignore_ascii_case(name))
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, name.to_string()))?;
+        let mut file = self.archive.by_index(index)?;
+        let mut bytes = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn parse_difficulty(&mut self, name: &str) -> io::Result<OsuFile> {
+        let bytes = self.read_file(name)?;
+        Ok(parse_osu_reader(bytes.as_slice()))
+    }
+
+    pub fn parse_difficulties(&mut self) -> io::Result<Vec<(String, OsuFile)>> {
+        self.difficulties()
+            .into_iter()
+            .map(|name| {
+                let osu = self.parse_difficulty(&name)?;
+                Ok((name, osu))
+            })
+            .collect()
+    }
+
+    /// Unpacks every file (difficulties, audio, backgrounds, hitsounds and
+    /// storyboards) into `dest`. Entries that would escape `dest` are skipped.
+    pub fn extract(&mut self, dest: &Path) -> io::Result<()> {
+        for i in 0..self.archive.len() {
+            let mut file = self.archive.by_index(i)?;
+            let Some(rel) = file.enclosed_name() else {
+                continue;
+            };
+            let out = dest.join(rel);
+            if file.is_dir() {
+                fs::create_dir_all(&out)?;
+                continue;
+            }
+            if let Some(parent) = out.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            io::copy(&mut file, &mut File::create(&out)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Imports an `.osz` into the songs folder, in a directory named after the
+/// archive, and returns that directory.
+pub fn import_osz(osz: &Path, songs_dir: &Path) -> io::Result<PathBuf> {
+    let name = osz
+        .file_stem()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Not an .osz file"))?;
+    let dest = songs_dir.join(name);
+    fs::create_dir_all(&dest)?;
+    OszArchive::open(osz)?.extract(&dest)?;
+    Ok(dest)
+}
+
+/// Packs a beatmap set directory back into an `.osz` archive. The archive
+/// may be written inside the set, in which case it leaves itself out.
+pub fn export_osz(set_dir: &Path, osz: &Path) -> io::Result<()> {
+    let mut zip = ZipWriter::new(File::create(osz)?);
+    let output = fs::canonicalize(osz)?;
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut pending = vec![set_dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut entries: Vec<PathBuf> = fs::read_dir(&dir)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<io::Result<_>>()?;
+        entries.sort();
+        for path in entries {
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            if fs::canonicalize(&path)? == output {
+                continue;
+            }
+            let rel = path.strip_prefix(set_dir).unwrap();
+            let name = rel
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            zip.start_file(name, options)?;
+            zip.write_all(&fs::read(&path)?)?;
+        }
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn export_inside_the_set_leaves_itself_out() {
+        let set = TempDir::new("osz_export");
+        fs::create_dir_all(set.join("sb")).unwrap();
+        fs::write(set.join("map.osu"), "osu file format v14\n").unwrap();
+        fs::write(set.join("sb").join("bg.png"), [1, 2, 3]).unwrap();
+
+        let osz = set.join("set.osz");
+        export_osz(set.path(), &osz).unwrap();
+        let mut archive = OszArchive::open(&osz).unwrap();
+        let mut names = archive.file_names();
+        names.sort();
+        assert_eq!(names, ["map.osu", "sb/bg.png"]);
+        assert_eq!(archive.read_file("SB/BG.PNG").unwrap(), [1, 2, 3]);
+    }
+}